@@ -0,0 +1,395 @@
+use std::{cell::RefCell, collections::HashSet};
+
+use log::*;
+use screeps::{
+    constants::{find, look, rampart_hits_max, Boost, Part, ResourceType, CREEP_LIFE_TIME, LAB_BOOST_ENERGY, LAB_BOOST_MINERAL},
+    enums::StructureObject,
+    game,
+    local::{ObjectId, Position},
+    objects::{Creep, Room, StructureLab, StructureStorage},
+    prelude::*,
+};
+
+use crate::memory;
+
+// hostiles seen siphoning energy out of our stores as of the last check, a separate threat
+// from the ones that come to fight
+thread_local! {
+    static SIPHONERS: RefCell<HashSet<ObjectId<Creep>>> = RefCell::new(HashSet::new());
+}
+
+// the bait hostiles seen so far, so each one only counts towards a drain attack once
+thread_local! {
+    static BAITS: RefCell<HashSet<ObjectId<Creep>>> = RefCell::new(HashSet::new());
+}
+
+// once hostiles have been in a room this long it's a siege, not a passing raid, and new
+// defenders get built to last rather than built cheap
+pub const SIEGE_TICKS: u32 = 100;
+
+// constructed walls only get repaired up to this many hits; they don't decay, so anything
+// past this is energy sunk into walls from all the way up to their 300M cap
+pub const WALL_HITS_CEILING: u32 = 100_000;
+
+// and ramparts up to this many; their hits max runs into the millions at higher levels, and
+// topping them all the way up would leave nothing for the roads and containers
+pub const RAMPART_HITS_CEILING: u32 = 100_000;
+
+// ramparts this close to the edge of the room are the ones covering its entrances
+const ENTRANCE_RAMPART_RANGE: u8 = 5;
+
+// count how many ticks in a row each owned room has had hostiles in it, kept in
+// `Memory.rooms[name].siege`; drops back to zero as soon as the room is clear
+pub fn track_siege(room: &Room) {
+    match room.controller() {
+        Some(controller) if controller.my() => {}
+        _ => return,
+    }
+    let room_memory = memory::room(room.name());
+    let siege = if room.find(find::HOSTILE_CREEPS, None).is_empty() {
+        0
+    } else {
+        memory::get_u32(&room_memory, "siege").unwrap_or(0) + 1
+    };
+    memory::set_u32(&room_memory, "siege", siege);
+}
+
+pub fn under_siege(room: &Room) -> bool {
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "siege").is_some_and(|siege| siege >= SIEGE_TICKS)
+}
+
+// a controller stays contested for this long after the last sign of anyone going for it, since
+// the claimers may only come by now and then
+const CONTESTED_COOLDOWN_TICKS: u32 = 300;
+
+// notice someone going for one of our controllers: a hostile with CLAIM parts in the room, an
+// upgrade block (which only `attackController` causes), or the downgrade timer dropping by more
+// than the one a tick it ticks down by on its own. the room stays contested until
+// `Memory.rooms[name].contestedUntil`, and the timer the last time it was checked is kept as
+// `downgrade`, with the tick that was as `downgradeAt`, so ticks the room wasn't checked on
+// (when the bot skipped them short of cpu) aren't mistaken for an attack, and the controller's
+// level then as `downgradeLevel`, since a level change resets the timer
+pub fn track_controller_attack(room: &Room) {
+    let Some(controller) = room.controller().filter(|controller| controller.my()) else {
+        return;
+    };
+    let room_memory = memory::room(room.name());
+    let downgrade = controller.ticks_to_downgrade();
+    let level = controller.level() as u32;
+    let same_level = memory::get_u32(&room_memory, "downgradeLevel") == Some(level);
+    let last = memory::get_u32(&room_memory, "downgrade").zip(memory::get_u32(&room_memory, "downgradeAt"));
+    let dropped = match (last, downgrade) {
+        (Some((last, at)), Some(now)) if same_level => now + game::time().saturating_sub(at) < last,
+        _ => false,
+    };
+    memory::set_u32(&room_memory, "downgrade", downgrade.unwrap_or(0));
+    memory::set_u32(&room_memory, "downgradeAt", game::time());
+    memory::set_u32(&room_memory, "downgradeLevel", level);
+    let claimers = room.find(find::HOSTILE_CREEPS, None).iter().any(|hostile| hostile.get_active_bodyparts(Part::Claim) > 0);
+    if claimers || controller.upgrade_blocked().is_some() || dropped {
+        memory::set_u32(&room_memory, "contestedUntil", game::time() + CONTESTED_COOLDOWN_TICKS);
+    }
+    let contested = controller_contested(room);
+    let was = memory::get_u32(&room_memory, "contested").unwrap_or(0) > 0;
+    if contested && !was {
+        warn!("{}: controller is under attack, putting defense and upgrading first", room.name());
+    } else if !contested && was {
+        info!("{}: controller has been left alone for {} ticks, back to normal", room.name(), CONTESTED_COOLDOWN_TICKS);
+    }
+    memory::set_u32(&room_memory, "contested", contested as u32);
+}
+
+pub fn controller_contested(room: &Room) -> bool {
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "contestedUntil").is_some_and(|until| game::time() < until)
+}
+
+// the biggest defender body we can afford; normally that's cheap ATTACK and MOVE, but under
+// siege it carries TOUGH to soak damage and HEAL to keep itself going (falling back to the
+// cheap body if we can't afford the tanky one at all)
+pub fn defender_body(energy: u32, sieged: bool) -> Vec<Part> {
+    let siege_unit = [Part::Tough, Part::Tough, Part::Move, Part::Move, Part::Move, Part::Attack, Part::Heal];
+    let cheap_unit = [Part::Tough, Part::Move, Part::Move, Part::Attack, Part::Attack];
+    let unit: &[Part] = if sieged && energy >= unit_cost(&siege_unit) { &siege_unit } else { &cheap_unit };
+
+    let mut body = Vec::new();
+    let mut cost = 0;
+    while cost + unit_cost(unit) <= energy && body.len() + unit.len() <= 50 {
+        body.extend_from_slice(unit);
+        cost += unit_cost(unit);
+    }
+    // TOUGH in front to take the hits first, HEAL at the back to be the last thing to go
+    body.sort_by_key(|part| match part {
+        Part::Tough => 0,
+        Part::Heal => 2,
+        _ => 1,
+    });
+    body
+}
+
+fn unit_cost(unit: &[Part]) -> u32 {
+    unit.iter().map(|part| part.cost()).sum()
+}
+
+// a lab in the room holding enough of a TOUGH boost (and energy) to boost all of this creep's
+// unboosted TOUGH parts; only fresh creeps bother, so one that failed to get boosted doesn't
+// keep going back to the lab for the rest of its life
+pub fn tough_boost_lab(creep: &Creep, room: &Room) -> Option<StructureLab> {
+    if creep.ticks_to_live().is_none_or(|ttl| ttl < CREEP_LIFE_TIME - 100) {
+        return None;
+    }
+    let unboosted = creep.body().iter().filter(|part| part.part() == Part::Tough && part.boost().is_none() && part.hits() > 0).count() as u32;
+    if unboosted == 0 {
+        return None;
+    }
+    room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .find_map(|s| match s {
+            StructureObject::StructureLab(lab) => {
+                let mineral = lab.mineral_type()?;
+                let enough = matches!(mineral.boost(), Some(Boost::Tough(_)))
+                    && lab.store().get_used_capacity(Some(mineral)) >= unboosted * LAB_BOOST_MINERAL
+                    && lab.store().get_used_capacity(Some(ResourceType::Energy)) >= unboosted * LAB_BOOST_ENERGY;
+                enough.then_some(lab)
+            }
+            _ => None,
+        })
+}
+
+// the entrance rampart a defender should hold - out of our ramparts near the edge of the room
+// that a creep can actually stand on, the one closest to wherever the hostiles are coming in,
+// skipping any another defender has already taken; None means there's nothing to hold and the
+// defender should go out and fight in the open
+pub fn entrance_rampart(room: &Room, taken: &[Position]) -> Option<Position> {
+    let hostiles = room.find(find::HOSTILE_CREEPS, None);
+    if hostiles.is_empty() {
+        return None;
+    }
+    room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .filter_map(|s| match s {
+            StructureObject::StructureRampart(rampart) => Some(rampart.pos()),
+            _ => None,
+        })
+        .filter(|pos| {
+            let (x, y) = (pos.x().u8(), pos.y().u8());
+            x.min(49 - x).min(y).min(49 - y) <= ENTRANCE_RAMPART_RANGE
+        })
+        .filter(|pos| !taken.contains(pos))
+        .filter(|pos| {
+            // a rampart over a wall or some other building can't be stood on
+            room.look_for_at_xy(look::STRUCTURES, pos.x().u8(), pos.y().u8())
+                .iter()
+                .all(|s| matches!(s, StructureObject::StructureRampart(_) | StructureObject::StructureRoad(_) | StructureObject::StructureContainer(_)))
+        })
+        .min_by_key(|pos| hostiles.iter().map(|hostile| hostile.pos().get_range_to(*pos)).min().unwrap_or(u32::MAX))
+}
+
+// a hostile with CARRY parts standing next to one of our containers, storage, terminal or links
+// is (most likely) there to help itself to the energy
+fn is_siphoning(hostile: &Creep) -> bool {
+    if hostile.get_active_bodyparts(Part::Carry) == 0 {
+        return false;
+    }
+    hostile.pos().find_in_range(find::STRUCTURES, 1).iter().any(|s| {
+        matches!(
+            s,
+            StructureObject::StructureContainer(_) | StructureObject::StructureStorage(_) | StructureObject::StructureTerminal(_) | StructureObject::StructureLink(_)
+        )
+    })
+}
+
+// look over every room for siphoning hostiles, logging the ones we haven't seen at it before
+pub fn track_siphoners() {
+    SIPHONERS.with(|siphoners_refcell| {
+        let mut siphoners = siphoners_refcell.borrow_mut();
+        let mut current = HashSet::new();
+        for room in game::rooms().values() {
+            for hostile in room.find(find::HOSTILE_CREEPS, None).iter().filter(|hostile| is_siphoning(hostile)) {
+                if let Some(id) = hostile.try_id() {
+                    if !siphoners.contains(&id) {
+                        warn!("{}: {} is siphoning energy at {:?}!", room.name(), hostile.owner().username(), hostile.pos());
+                    }
+                    current.insert(id);
+                }
+            }
+        }
+        *siphoners = current;
+    });
+}
+
+pub fn is_siphoner(hostile: &Creep) -> bool {
+    hostile.try_id().is_some_and(|id| SIPHONERS.with(|siphoners| siphoners.borrow().contains(&id)))
+}
+
+// the hits a structure gets repaired up to, or None if it can't be repaired at all; walls
+// stop at `WALL_HITS_CEILING` and ramparts at `RAMPART_HITS_CEILING`, and the indestructible
+// walls some rooms come with (which have no hits to speak of) aren't repairable
+pub fn repair_ceiling(structure: &StructureObject) -> Option<u32> {
+    let repairable = structure.as_repairable()?;
+    match structure {
+        StructureObject::StructureWall(_) if repairable.hits_max() == 0 => None,
+        StructureObject::StructureWall(_) => Some(repairable.hits_max().min(WALL_HITS_CEILING)),
+        StructureObject::StructureRampart(_) => Some(repairable.hits_max().min(RAMPART_HITS_CEILING)),
+        _ => Some(repairable.hits_max()),
+    }
+}
+
+// whether a structure is below its repair ceiling
+pub fn needs_repair(structure: &StructureObject) -> bool {
+    repair_ceiling(structure).is_some_and(|ceiling| structure.as_structure().hits() < ceiling)
+}
+
+// a room whose storage gets this full (as a percentage) sinks its surplus into reinforcing its
+// walls and ramparts, and carries on until the storage is back down below
+// `REINFORCE_STOP_PERCENT`
+const REINFORCE_START_PERCENT: u32 = 90;
+const REINFORCE_STOP_PERCENT: u32 = 75;
+
+// a storage with less room than this left stops taking energy, so carriers don't walk over with
+// loads it can't fit; with the storage that full the surplus goes into the walls instead (and
+// gets sold, past `market::STORAGE_SELL_THRESHOLD`)
+const STORAGE_FULL_MARGIN: u32 = 5000;
+
+pub fn storage_nearly_full(storage: &StructureStorage) -> bool {
+    storage.store().get_free_capacity(Some(ResourceType::Energy)).max(0) < STORAGE_FULL_MARGIN as i32
+}
+
+// switch each owned room in and out of reinforcing, kept in `Memory.rooms[name].reinforcing`;
+// a nearly full storage always starts it, whatever else is filling it
+pub fn track_reinforcing(room: &Room) {
+    let Some(storage) = room.storage() else {
+        return;
+    };
+    let room_memory = memory::room(room.name());
+    let store = storage.store();
+    let fill = store.get_used_capacity(Some(ResourceType::Energy)) * 100 / store.get_capacity(None).max(1);
+    let was = memory::get_u32(&room_memory, "reinforcing").unwrap_or(0) > 0;
+    let reinforcing = storage_nearly_full(&storage) || if was { fill >= REINFORCE_STOP_PERCENT } else { fill >= REINFORCE_START_PERCENT };
+    if reinforcing && !was {
+        info!("{}: storage is {}% full, reinforcing walls with the surplus", room.name(), fill);
+    } else if !reinforcing && was {
+        info!("{}: storage is down to {}%, done reinforcing", room.name(), fill);
+    }
+    memory::set_u32(&room_memory, "reinforcing", reinforcing as u32);
+}
+
+pub fn reinforcing(room: &Room) -> bool {
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "reinforcing").unwrap_or(0) > 0
+}
+
+// while reinforcing, walls and ramparts get built up past their usual repair ceiling to the
+// most a rampart can have at the room's level
+pub fn needs_reinforcing(structure: &StructureObject) -> bool {
+    if !matches!(structure, StructureObject::StructureWall(_) | StructureObject::StructureRampart(_)) || repair_ceiling(structure).is_none() {
+        return false;
+    }
+    let Some(room) = structure.as_structure().room() else {
+        return false;
+    };
+    if !reinforcing(&room) {
+        return false;
+    }
+    let level = room.controller().map_or(0, |controller| controller.level() as u32);
+    let ceiling = rampart_hits_max(level).min(structure.as_structure().hits_max());
+    structure.as_structure().hits() < ceiling
+}
+
+// a drain attack sends in cheap creeps that can soak up tower fire but do no harm themselves,
+// until the towers are empty and the real attack comes in. this many bait creeps within
+// `DRAIN_WINDOW_TICKS` of each other gets the room's towers holding their fire, until the room
+// has gone that long without seeing another one. the counts are kept in `Memory.rooms[name]` as
+// `baits`, `baitsSince` and `drainUntil`, and `Memory.rooms[name].drainDefense = 0` turns it off
+const DRAIN_BAITS: u32 = 3;
+const DRAIN_WINDOW_TICKS: u32 = 1500;
+
+// bait carries no more than this many ATTACK and RANGED_ATTACK parts between them
+const BAIT_ATTACK_PARTS: usize = 2;
+
+// a hostile built to take hits and walk, with a token attack part or two so it passes for a
+// threat but nothing to dismantle, claim or carry off energy with. a creep with no attack at
+// all isn't bait: a pure healer is there to keep the real attackers alive
+pub fn is_bait(hostile: &Creep) -> bool {
+    bait_body(&hostile.body().iter().map(|part| part.part()).collect::<Vec<_>>())
+}
+
+fn bait_body(body: &[Part]) -> bool {
+    let attack = body.iter().filter(|part| matches!(part, Part::Attack | Part::RangedAttack)).count();
+    (1..=BAIT_ATTACK_PARTS).contains(&attack)
+        && body.iter().all(|part| matches!(part, Part::Tough | Part::Move | Part::Heal | Part::Attack | Part::RangedAttack))
+}
+
+fn drain_defense_enabled(room: &Room) -> bool {
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "drainDefense").is_none_or(|enabled| enabled > 0)
+}
+
+// count the bait hostiles turning up in our rooms, warning when it starts to look like a drain
+pub fn track_drainers() {
+    BAITS.with(|baits_refcell| {
+        let mut baits = baits_refcell.borrow_mut();
+        let mut current = HashSet::new();
+        for room in game::rooms().values() {
+            if !room.controller().is_some_and(|controller| controller.my()) || !drain_defense_enabled(&room) {
+                continue;
+            }
+            let room_memory = memory::room(room.name());
+            for hostile in room.find(find::HOSTILE_CREEPS, None).iter().filter(|hostile| is_bait(hostile)) {
+                let Some(id) = hostile.try_id() else {
+                    continue;
+                };
+                current.insert(id);
+                if baits.contains(&id) {
+                    continue;
+                }
+                let now = game::time();
+                let since = memory::get_u32(&room_memory, "baitsSince").unwrap_or(0);
+                let count = if now > since + DRAIN_WINDOW_TICKS {
+                    memory::set_u32(&room_memory, "baitsSince", now);
+                    1
+                } else {
+                    memory::get_u32(&room_memory, "baits").unwrap_or(0) + 1
+                };
+                memory::set_u32(&room_memory, "baits", count);
+                if count >= DRAIN_BAITS {
+                    if !drain_suspected(&room) {
+                        warn!("{}: {} bait creeps from {} in a row, towers holding fire against them", room.name(), count, hostile.owner().username());
+                    }
+                    memory::set_u32(&room_memory, "drainUntil", now + DRAIN_WINDOW_TICKS);
+                }
+            }
+        }
+        *baits = current;
+    });
+}
+
+// whether the room looks to be under a drain attack, and its towers should only fire on
+// hostiles that are an actual threat
+pub fn drain_suspected(room: &Room) -> bool {
+    if !drain_defense_enabled(room) {
+        return false;
+    }
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "drainUntil").is_some_and(|until| game::time() < until)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_tank_with_a_token_attack_is_bait() {
+        assert!(bait_body(&[Part::Tough, Part::Tough, Part::Move, Part::Move, Part::Attack, Part::Heal]));
+        assert!(bait_body(&[Part::Tough, Part::Move, Part::RangedAttack]));
+    }
+
+    #[test]
+    fn healers_and_real_attackers_are_not_bait() {
+        assert!(!bait_body(&[Part::Heal, Part::Heal, Part::Move, Part::Move]));
+        assert!(!bait_body(&[Part::Tough, Part::Move]));
+        assert!(!bait_body(&[Part::Attack, Part::Attack, Part::RangedAttack, Part::Move]));
+        assert!(!bait_body(&[Part::Work, Part::Attack, Part::Move]));
+    }
+}
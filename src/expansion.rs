@@ -0,0 +1,86 @@
+use js_sys::Object;
+use screeps::{
+    constants::Terrain,
+    game,
+    local::{Position, RoomName},
+    objects::RoomTerrain,
+};
+
+use crate::memory;
+
+// how good a room would be to claim, going by its terrain: room to fit a base, space to work
+// its sources and controller from, and how few exits it has to hold. scouted rooms get a
+// `layout` score from this alongside their remote `score`, and the two together make their
+// `claimScore` in `Memory.scouted[room]`
+
+// a base needs tiles with no wall this close to them to fit its core, and a room with this
+// many of them gets the full `OPEN_SPACE_SCORE`
+const BASE_CLEARANCE: i32 = 3;
+const BASE_SPOTS_FOR_FULL_SCORE: u32 = 200;
+const OPEN_SPACE_SCORE: u32 = 20;
+
+// every walkable tile around a source or the controller is worth this much, up to
+// `ACCESS_TILES` of them each
+const ACCESS_SCORE: u32 = 2;
+const ACCESS_TILES: u32 = 3;
+
+// every this many walkable tiles along the edge of the room is another way in to defend, and
+// takes a point off
+const EXIT_TILES_PER_POINT: u32 = 10;
+
+fn walkable(terrain: &RoomTerrain, x: i32, y: i32) -> bool {
+    (0..50).contains(&x) && (0..50).contains(&y) && terrain.get(x as u8, y as u8) != Terrain::Wall
+}
+
+fn access_tiles(terrain: &RoomTerrain, pos: Position) -> u32 {
+    let (x, y) = (pos.x().u8() as i32, pos.y().u8() as i32);
+    let mut tiles = 0;
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            if (dx != 0 || dy != 0) && walkable(terrain, x + dx, y + dy) {
+                tiles += 1;
+            }
+        }
+    }
+    tiles.min(ACCESS_TILES)
+}
+
+// how well a base would fit in the room, or 0 if it's too cramped to fit one at all
+pub fn layout_score(room_name: RoomName, sources: &[Position], controller: Option<Position>) -> u32 {
+    let Some(terrain) = game::map::get_room_terrain(room_name) else {
+        return 0;
+    };
+    let Some(controller) = controller else {
+        return 0;
+    };
+
+    let margin = BASE_CLEARANCE + 1;
+    let mut base_spots = 0;
+    for x in margin..50 - margin {
+        for y in margin..50 - margin {
+            let clear = (-BASE_CLEARANCE..=BASE_CLEARANCE).all(|dx| (-BASE_CLEARANCE..=BASE_CLEARANCE).all(|dy| walkable(&terrain, x + dx, y + dy)));
+            if clear {
+                base_spots += 1;
+            }
+        }
+    }
+    if base_spots == 0 {
+        return 0;
+    }
+
+    let open_space = base_spots.min(BASE_SPOTS_FOR_FULL_SCORE) * OPEN_SPACE_SCORE / BASE_SPOTS_FOR_FULL_SCORE;
+    let access = sources.iter().chain([&controller]).map(|pos| access_tiles(&terrain, *pos) * ACCESS_SCORE).sum::<u32>();
+    let exit_tiles = (0..50).map(|i| [(i, 0), (i, 49), (0, i), (49, i)].iter().filter(|(x, y)| walkable(&terrain, *x, *y)).count() as u32).sum::<u32>();
+    (open_space + access).saturating_sub(exit_tiles / EXIT_TILES_PER_POINT)
+}
+
+// the scouted room that'd be best to claim next, with its score, if any of them are worth it
+pub fn best_claim(scouted: &Object) -> Option<(String, u32)> {
+    memory::keys(scouted)
+        .into_iter()
+        .filter_map(|room| {
+            let score = memory::get_u32(&memory::child(scouted, &room), "claimScore")?;
+            (score > 0).then_some((room, score))
+        })
+        .max_by_key(|(_, score)| *score)
+}
@@ -0,0 +1,264 @@
+use js_sys::{JsString, Object, Reflect};
+use log::*;
+use screeps::{
+    constants::{find, look, Part, StructureType},
+    enums::StructureObject,
+    game,
+    local::{Position, RoomCoordinate, RoomName},
+    objects::{Creep, Room},
+    prelude::*,
+};
+use wasm_bindgen::JsCast;
+
+use crate::{memory, movement, role::Role, squad};
+
+// harassing the neighbours: every so often a room sends an attack squad (see `squad`) into a
+// next-door room another player owns or is remote mining, to kill their miners and knock down
+// whatever they've left unprotected. it's aggressive, so it's off unless `Memory.harass = 1`.
+// whatever we've seen of a player's room is kept in `Memory.enemies[room]` as `{ owner,
+// strength, at }`, `strength` being the ATTACK, RANGED_ATTACK and HEAL parts of their creeps
+// there plus `TOWER_STRENGTH` a tower; raiders carry `raid` (the room they're raiding) and
+// `home` in their memory alongside their squad (and `raidOver` once they're on their way back),
+// and `Memory.rooms[home].raidedAt` is when the last raid set off
+
+// how long a room waits after sending a raid off before sending another
+const RAID_INTERVAL_TICKS: u32 = 5000;
+
+// what a tower counts for against the raiders; enough that any room with a working tower is
+// left well alone
+const TOWER_STRENGTH: u32 = 20;
+
+// what we know of a room stops counting after this long, or the strength of a room that ran a
+// raid off would keep it safe forever
+const ENEMY_INFO_TICKS: u32 = 20_000;
+
+fn enabled() -> bool {
+    memory::get_u32(&screeps::memory::ROOT, "harass").is_some_and(|harass| harass > 0)
+}
+
+fn enemies() -> Object {
+    memory::child(&screeps::memory::ROOT, "enemies")
+}
+
+fn creep_memory(creep: &Creep) -> Option<Object> {
+    let memory = creep.memory();
+    memory.is_object().then(|| memory.unchecked_into())
+}
+
+fn center(room: RoomName) -> Position {
+    Position::new(RoomCoordinate::new(25).unwrap(), RoomCoordinate::new(25).unwrap(), room)
+}
+
+// the room a creep is raiding, if it's a raider; raiders are run here rather than being given
+// targets like everything else
+pub fn raid_of(creep: &Creep) -> Option<RoomName> {
+    memory::get_string(&creep_memory(creep)?, "raid")?.parse().ok()
+}
+
+fn raid_home(creep: &Creep) -> Option<RoomName> {
+    memory::get_string(&creep_memory(creep)?, "home")?.parse().ok()
+}
+
+// the squad raids from `home` go out as
+fn raid_squad(home: RoomName) -> String {
+    format!("raid-{}", home)
+}
+
+// add `raid`, `home` and the raid's squad to the memory of a creep about to be spawned as a
+// raider
+pub fn tag_memory(memory: &Object, home: RoomName, raid: RoomName) {
+    memory::set_string(memory, "home", &home.to_string());
+    memory::set_string(memory, "raid", &raid.to_string());
+    squad::tag_memory(memory, &raid_squad(home));
+}
+
+fn combat_parts(creep: &Creep) -> u32 {
+    creep.body().iter()
+        .filter(|part| part.hits() > 0 && matches!(part.part(), Part::Attack | Part::RangedAttack | Part::Heal))
+        .count() as u32
+}
+
+// how much fight a room we can see has in it, from its owner's creeps and towers
+fn strength(room: &Room) -> u32 {
+    let creeps = room.find(find::HOSTILE_CREEPS, None).iter().map(combat_parts).sum::<u32>();
+    let towers = room.find(find::HOSTILE_STRUCTURES, None)
+        .iter()
+        .filter(|s| match s {
+            StructureObject::StructureTower(tower) => tower.store().get_used_capacity(None) > 0,
+            _ => false,
+        })
+        .count() as u32;
+    creeps + towers * TOWER_STRENGTH
+}
+
+// the other player a room belongs to, whether they own it or are only reserving it to mine;
+// invader cores reserve rooms too, but there's nobody behind those to harass
+fn enemy_owner(room: &Room) -> Option<String> {
+    let ours = game::spawns().values().next().and_then(|spawn| spawn.owner()).map(|owner| owner.username());
+    let controller = room.controller()?;
+    let owner = controller.owner().map(|owner| owner.username())
+        .or_else(|| controller.reservation().map(|reservation| reservation.username()))?;
+    (Some(&owner) != ours.as_ref() && owner != "Invader").then_some(owner)
+}
+
+// note down how much fight another player's room put up, whenever we can see it
+pub fn record_room(room: &Room) {
+    let Some(owner) = enemy_owner(room) else {
+        return;
+    };
+    let enemy_memory = memory::child(&enemies(), &room.name().to_string());
+    memory::set_string(&enemy_memory, "owner", &owner);
+    memory::set_u32(&enemy_memory, "strength", strength(room));
+    memory::set_u32(&enemy_memory, "at", game::time());
+}
+
+// the strength a raid from `home` would go in with
+fn raid_strength(home: &Room) -> u32 {
+    let body = squad::body(Role::Defender, home.energy_capacity_available());
+    body.iter().filter(|part| **part == Part::Attack).count() as u32 * squad::SQUAD_ATTACKERS as u32
+}
+
+// the weakest room next to `home` that we know another player is using and that a raid could
+// take on, if any
+fn raid_target(home: &Room) -> Option<RoomName> {
+    let enemies = enemies();
+    let ours = raid_strength(home);
+    memory::keys(&enemies)
+        .into_iter()
+        .filter_map(|key| {
+            let room = key.parse::<RoomName>().ok()?;
+            let enemy_memory = memory::child(&enemies, &key);
+            let at = memory::get_u32(&enemy_memory, "at")?;
+            if game::time() > at + ENEMY_INFO_TICKS || game::map::get_room_linear_distance(home.name(), room, false) != 1 {
+                return None;
+            }
+            let strength = memory::get_u32(&enemy_memory, "strength").unwrap_or(0);
+            (strength < ours).then_some((room, strength))
+        })
+        .min_by_key(|(_, strength)| *strength)
+        .map(|(room, _)| room)
+}
+
+fn raiders() -> Vec<Creep> {
+    game::creeps().values().filter(|creep| raid_of(creep).is_some()).collect()
+}
+
+// the next raider `home` should spawn and the room it's to raid, if a raid is due
+pub fn needed_raider(home: &Room) -> Option<(Role, RoomName)> {
+    if !enabled() {
+        return None;
+    }
+    let name = raid_squad(home.name());
+    squad::reset_if_wiped_out(&name);
+    let gathering = squad::members(&name);
+    if let Some(raid) = gathering.first().and_then(raid_of) {
+        return squad::next_member(&name).map(|role| (role, raid));
+    }
+    let raided_at = memory::get_u32(&memory::room(home.name()), "raidedAt");
+    if raided_at.is_some_and(|at| game::time() < at + RAID_INTERVAL_TICKS) {
+        return None;
+    }
+    Some((squad::next_member(&name)?, raid_target(home)?))
+}
+
+// send a raid's squad home, where its members stand down for other work
+fn disband(home: RoomName) {
+    let name = raid_squad(home);
+    for creep in squad::members(&name) {
+        if let Some(memory) = creep_memory(&creep) {
+            memory::set_u32(&memory, "raidOver", 1);
+        }
+    }
+    squad::disband(&name);
+}
+
+fn returning(creep: &Creep) -> bool {
+    creep_memory(creep).is_some_and(|memory| memory::get_u32(&memory, "raidOver").unwrap_or(0) > 0)
+}
+
+pub fn run_raiders() {
+    let (returning, raiders): (Vec<_>, Vec<_>) = raiders().into_iter().partition(returning);
+    for creep in returning.iter().filter(|creep| !creep.spawning()) {
+        match raid_home(creep) {
+            Some(home) if creep.pos().room_name() != home => {
+                let _ = movement::travel_to(creep, center(home));
+            }
+            _ => {
+                if let Some(memory) = creep_memory(creep) {
+                    let _ = Reflect::delete_property(&memory, &JsString::from("raid"));
+                    let _ = Reflect::delete_property(&memory, &JsString::from("raidOver"));
+                }
+            }
+        }
+    }
+    let mut raids: Vec<(RoomName, RoomName)> = raiders.iter()
+        .filter_map(|creep| Some((raid_home(creep)?, raid_of(creep)?)))
+        .collect();
+    raids.sort_by_key(|(home, raid)| (home.to_string(), raid.to_string()));
+    raids.dedup_by_key(|(home, _)| *home);
+
+    for (home, raid) in raids {
+        let name = raid_squad(home);
+        let members = squad::members(&name);
+        let room = game::rooms().get(raid);
+        if let Some(room) = &room {
+            record_room(room);
+        }
+        let in_room = members.iter().filter(|creep| creep.pos().room_name() == raid).collect::<Vec<_>>();
+        if squad::advancing(&name) {
+            if let Some(room) = &room {
+                // pull out if they've more fight in them than we have
+                let ours = in_room.iter().map(|creep| combat_parts(creep)).sum::<u32>();
+                if strength(room) > ours && ours > 0 {
+                    warn!("{}: {} is too strong for our raid, pulling out", home, raid);
+                    disband(home);
+                    continue;
+                }
+            }
+        }
+        // the squad heads for the room, and once it's in, goes for whatever's most worth hitting
+        let target = room.as_ref()
+            .zip(squad::leader(&members).filter(|leader| leader.pos().room_name() == raid))
+            .map(|(room, leader)| raid_target_in(leader, room));
+        if let Some(None) = target {
+            info!("{}: nothing left to hit in {}, raid over", home, raid);
+            disband(home);
+            continue;
+        }
+        let objective = target.flatten().unwrap_or_else(|| center(raid));
+        if squad::run(&name, rally_point(home), objective) {
+            warn!("{}: raiding {} with {} raiders", home, raid, members.len());
+            memory::set_u32(&memory::room(home), "raidedAt", game::time());
+        }
+    }
+}
+
+// where a raid forms up before setting off: by the home room's spawn
+fn rally_point(home: RoomName) -> Position {
+    game::rooms().get(home)
+        .and_then(|room| room.find(find::MY_SPAWNS, None).first().map(|spawn| spawn.pos()))
+        .unwrap_or_else(|| center(home))
+}
+
+// what a raid goes for: their creeps (miners and haulers first, they can't hit back), then
+// structures that aren't under a rampart, and the containers they're mining into
+fn raid_target_in(creep: &Creep, room: &Room) -> Option<Position> {
+    let hostiles = room.find(find::HOSTILE_CREEPS, None);
+    let hostile = hostiles.iter()
+        .min_by_key(|hostile| (combat_parts(hostile) > 0, creep.pos().get_range_to(hostile.pos())));
+    if let Some(hostile) = hostile {
+        return Some(hostile.pos());
+    }
+    let unprotected = |s: &StructureObject| {
+        s.pos().look_for(look::STRUCTURES).is_ok_and(|structures| {
+            !structures.iter().any(|other| other.structure_type() == StructureType::Rampart)
+        })
+    };
+    room.find(find::HOSTILE_STRUCTURES, None)
+        .into_iter()
+        .filter(|s| !matches!(s, StructureObject::StructureController(_) | StructureObject::StructureRampart(_)))
+        .chain(room.find(find::STRUCTURES, None).into_iter().filter(|s| matches!(s, StructureObject::StructureContainer(_))))
+        .filter(unprotected)
+        .min_by_key(|s| creep.pos().get_range_to(s.pos()))
+        .map(|s| s.pos())
+}
@@ -0,0 +1,59 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{constants::ErrorCode, game, objects::Creep, prelude::*};
+
+#[derive(Default)]
+struct CreepIntents {
+    moved: bool,
+    acted: bool,
+}
+
+// what each creep has already done this tick, alongside the tick it's for, so that no creep
+// issues more than one move and one action per tick no matter how many code paths want it to
+thread_local! {
+    static INTENTS: RefCell<(u32, HashMap<String, CreepIntents>)> = RefCell::new((0, HashMap::new()));
+}
+
+fn with_intents<T>(creep: &Creep, f: impl FnOnce(&mut CreepIntents) -> T) -> T {
+    INTENTS.with(|intents_refcell| {
+        let mut intents = intents_refcell.borrow_mut();
+        if intents.0 != game::time() {
+            *intents = (game::time(), HashMap::new());
+        }
+        f(intents.1.entry(creep.name()).or_default())
+    })
+}
+
+fn claim(creep: &Creep, kind: &str, slot: fn(&mut CreepIntents) -> &mut bool) -> bool {
+    with_intents(creep, |creep_intents| {
+        let taken = slot(creep_intents);
+        if *taken {
+            debug!("{}: already issued a {} this tick, suppressing another", creep.name(), kind);
+            false
+        } else {
+            *taken = true;
+            true
+        }
+    })
+}
+
+// claim this tick's movement for the creep, false if it's already moving somewhere
+pub fn claim_move(creep: &Creep) -> bool {
+    claim(creep, "move", |intents| &mut intents.moved)
+}
+
+// run `action` as the creep's one action for this tick, or fail with `ErrorCode::Busy`
+// without running it if it's already done something; an action the game refused doesn't
+// count, so another code path can still have the creep do something else this tick
+pub fn act(creep: &Creep, action: impl FnOnce() -> Result<(), ErrorCode>) -> Result<(), ErrorCode> {
+    if with_intents(creep, |creep_intents| creep_intents.acted) {
+        debug!("{}: already issued an action this tick, suppressing another", creep.name());
+        return Err(ErrorCode::Busy);
+    }
+    let result = action();
+    if result.is_ok() {
+        with_intents(creep, |creep_intents| creep_intents.acted = true);
+    }
+    result
+}
@@ -0,0 +1,147 @@
+use js_sys::{JsString, Reflect};
+use log::*;
+use screeps::{
+    constants::{find, ErrorCode, ResourceType, LAB_REACTION_AMOUNT, LAB_REACTION_RANGE},
+    enums::StructureObject,
+    local::ObjectId,
+    objects::{Room, StructureLab},
+    prelude::*,
+};
+
+use crate::memory;
+
+// what each lab is for, set by hand as `Memory.labs[id]`: an input lab is given the mineral it
+// should be kept stocked with (`"H"`), and an output lab `"output"`, which runs the reaction
+// between the input labs in range of it. haulers keep the inputs filled and take the products
+// away to storage; labs without an entry are left alone
+
+// an output lab's product is taken away once there's this much of it, so haulers don't make
+// a trip for every reaction
+const PRODUCT_BATCH: u32 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabRole {
+    Input(ResourceType),
+    Output,
+}
+
+pub fn lab_role(lab: &StructureLab) -> Option<LabRole> {
+    let labs = memory::child(&screeps::memory::ROOT, "labs");
+    let value = Reflect::get(&labs, &JsString::from(lab.id().to_string())).ok()?;
+    if value.as_string().as_deref() == Some("output") {
+        return Some(LabRole::Output);
+    }
+    ResourceType::from_js_value(&value).map(LabRole::Input)
+}
+
+// the mineral `lab` is meant to hold, if it's an input lab
+pub fn required_mineral(lab: &StructureLab) -> Option<ResourceType> {
+    match lab_role(lab)? {
+        LabRole::Input(mineral) => Some(mineral),
+        LabRole::Output => None,
+    }
+}
+
+fn labs(room: &Room) -> Vec<StructureLab> {
+    room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .filter_map(|s| match s {
+            StructureObject::StructureLab(lab) => Some(lab),
+            _ => None,
+        })
+        .collect()
+}
+
+// what `a` and `b` react into, if they react at all
+fn product(a: ResourceType, b: ResourceType) -> Option<ResourceType> {
+    enum_iterator::all::<ResourceType>()
+        .find(|product| matches!(product.reaction_components(), Some([x, y]) if (x, y) == (a, b) || (x, y) == (b, a)))
+}
+
+// run every output lab that's off cooldown on a pair of stocked input labs in range of it whose
+// minerals react with each other
+pub fn run_reactions(room: &Room) {
+    let labs = labs(room);
+    for output in labs.iter().filter(|lab| lab_role(lab) == Some(LabRole::Output) && lab.cooldown() == 0) {
+        let inputs = labs.iter()
+            .filter(|lab| matches!(lab_role(lab), Some(LabRole::Input(_))) && lab.pos().in_range_to(output.pos(), LAB_REACTION_RANGE))
+            .filter_map(|lab| lab.mineral_type().filter(|&mineral| lab.store().get_used_capacity(Some(mineral)) >= LAB_REACTION_AMOUNT).map(|mineral| (lab, mineral)))
+            .collect::<Vec<_>>();
+        let pair = inputs.iter()
+            .enumerate()
+            .flat_map(|(index, first)| inputs[index + 1..].iter().map(move |second| (first, second)))
+            .find(|((_, a), (_, b))| product(*a, *b).is_some());
+        let Some(((first, _), (second, _))) = pair else {
+            debug!("lab {} is waiting on its inputs", output.id());
+            continue;
+        };
+        match output.run_reaction(first, second) {
+            Ok(()) => debug!("lab {} reacting {:?} and {:?}", output.id(), first.mineral_type(), second.mineral_type()),
+            // running short of inputs, or full up with product, sorts itself out once the
+            // haulers have been round
+            Err(ErrorCode::NotEnough) | Err(ErrorCode::Full) | Err(ErrorCode::Tired) => {}
+            Err(e) => warn!("lab {} couldn't run a reaction: {:?}", output.id(), e),
+        }
+    }
+}
+
+// a lab with something in it that should be taken away to storage, skipping any in `taken`
+// that a hauler is already emptying: an output lab with a batch of product in it, or an input
+// lab left holding a mineral other than its own, which would otherwise never get refilled
+pub fn next_product(room: &Room, taken: &[ObjectId<StructureLab>]) -> Option<(ObjectId<StructureLab>, ResourceType)> {
+    labs(room).into_iter()
+        .filter(|lab| !taken.contains(&lab.id()))
+        .find_map(|lab| {
+            let held = lab.mineral_type()?;
+            let amount = lab.store().get_used_capacity(Some(held));
+            let empty = match lab_role(&lab)? {
+                LabRole::Output => amount >= PRODUCT_BATCH,
+                LabRole::Input(mineral) => held != mineral && amount > 0,
+            };
+            empty.then(|| (lab.id(), held))
+        })
+}
+
+// a lab in the room that needs more of its mineral brought to it, along with the mineral and
+// how much room there is for it, skipping any in `taken` that a hauler is already filling.
+// a lab already holding some other mineral is left alone, since a lab can only hold one at a
+// time and it'd just refuse the delivery
+pub fn next_request(room: &Room, taken: &[ObjectId<StructureLab>]) -> Option<(ObjectId<StructureLab>, ResourceType, u32)> {
+    labs(room).into_iter()
+        .filter(|lab| !taken.contains(&lab.id()))
+        .find_map(|lab| {
+            let mineral = required_mineral(&lab)?;
+            if let Some(current) = lab.mineral_type() {
+                if current != mineral {
+                    debug!("lab {} holds {:?} rather than its {:?}, not filling it until it's emptied", lab.id(), current, mineral);
+                    return None;
+                }
+            }
+            let missing = lab.store().get_free_capacity(Some(mineral)).max(0) as u32;
+            (missing > 0 && stock(room, mineral) > 0).then(|| (lab.id(), mineral, missing))
+        })
+}
+
+// how much of a resource the room has on hand to deliver, in its storage and terminal
+pub fn stock(room: &Room, resource: ResourceType) -> u32 {
+    let storage = room.storage().map_or(0, |storage| storage.store().get_used_capacity(Some(resource)));
+    let terminal = room.terminal().map_or(0, |terminal| terminal.store().get_used_capacity(Some(resource)));
+    storage + terminal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minerals_react_either_way_round() {
+        assert_eq!(product(ResourceType::Hydrogen, ResourceType::Oxygen), Some(ResourceType::Hydroxide));
+        assert_eq!(product(ResourceType::Oxygen, ResourceType::Hydrogen), Some(ResourceType::Hydroxide));
+    }
+
+    #[test]
+    fn minerals_that_dont_react_have_no_product() {
+        assert_eq!(product(ResourceType::Hydrogen, ResourceType::Hydrogen), None);
+        assert_eq!(product(ResourceType::Utrium, ResourceType::Keanium), None);
+    }
+}
@@ -1,47 +1,274 @@
 use std::{
     cell::RefCell,
-    collections::{hash_map::Entry, HashMap, HashSet},
+    collections::{HashMap, HashSet},
 };
 use js_sys::{JsString, Object, Reflect};
 use log::*;
 use screeps::{
-    constants::{look,ErrorCode, Part, ResourceType},
+    constants::{look,Direction, ErrorCode, Part, ResourceType, HARVEST_POWER, MAX_CREEP_SIZE},
     enums::{StructureObject},
     find, game,
-    local::{ObjectId,Position,RoomCoordinate},
-    objects::{Creep, Source, ConstructionSite, StructureController, StructureContainer, StructureExtension, StructureSpawn},
+    local::{ObjectId,Position,RoomName},
+    objects::{Creep, Room, Source, SpawnOptions, StructureController, StructureContainer, StructureLab, StructureSpawn},
     structure::{StructureType},
     prelude::*,
 };
 use wasm_bindgen::prelude::*;
 
+use role::Role;
+
+mod defense;
+mod expansion;
+mod harass;
+mod intents;
+mod labs;
+mod links;
 mod logging;
+mod market;
+mod memory;
+mod mining;
+mod movement;
+mod planner;
+mod population;
+mod remote;
+mod role;
+mod schedule;
+mod shuttle;
+mod squad;
+mod stamp;
+mod stats;
+mod strategy;
+mod terminal;
+mod tower;
+mod upgrading;
+mod visuals;
+mod watchdog;
 
 // this is one way to persist data between ticks within Rust's memory, as opposed to
 // keeping state in memory on game objects - but will be lost on global resets!
 thread_local! {
     static CREEP_TARGETS: RefCell<HashMap<String, CreepTarget>> = RefCell::new(HashMap::new());
+    // the tick to next try unlocking the cpu at, and how long to wait after that if it fails
+    static UNLOCK_BACKOFF: RefCell<(u32, u32)> = const { RefCell::new((0, UNLOCK_RETRY_TICKS)) };
+    // the tick to next try generating a pixel at, and how long to wait after that if it fails
+    #[cfg(feature = "generate-pixel")]
+    static PIXEL_BACKOFF: RefCell<(u32, u32)> = const { RefCell::new((0, PIXEL_RETRY_TICKS)) };
+    // the last `Memory.buildPriority` we complained about, so a bad one only gets logged once
+    static BAD_BUILD_PRIORITY: RefCell<Option<String>> = const { RefCell::new(None) };
+    // each creep's target as of the last check, and the tick it was first seen holding it
+    static TARGET_AGES: RefCell<HashMap<String, (CreepTarget, u32)>> = RefCell::new(HashMap::new());
 }
 
+// no task should take anywhere near this long, so a creep still on the same target after it is
+// stuck on something its branch never notices, and the target gets dropped for reassignment;
+// the targets creeps are meant to hold indefinitely are exempt
+const TARGET_TTL_TICKS: u32 = 500;
+
+// after a failed unlock, wait this long before trying again, doubling each time it fails
+// again up to `UNLOCK_RETRY_MAX_TICKS`
+const UNLOCK_RETRY_TICKS: u32 = 100;
+const UNLOCK_RETRY_MAX_TICKS: u32 = 10_000;
+
+// and the same for generating a pixel, which a full bucket should always be able to do, so a
+// failure is most likely a hiccup on the server's end
+#[cfg(feature = "generate-pixel")]
+const PIXEL_RETRY_TICKS: u32 = 100;
+#[cfg(feature = "generate-pixel")]
+const PIXEL_RETRY_MAX_TICKS: u32 = 10_000;
+
 static INIT_LOGGING: std::sync::Once = std::sync::Once::new();
 
+// a read-only summary of what everything is up to, for debugging from the console with
+// `diagnostics()`
+#[wasm_bindgen]
+pub fn diagnostics() -> String {
+    let mut lines = vec![format!("tick {}, cpu used {:.2}, bucket {}", game::time(), game::cpu::get_used(), game::cpu::bucket())];
+    CREEP_TARGETS.with(|creep_targets_refcell| {
+        let creep_targets = creep_targets_refcell.borrow();
+        lines.push(format!("{} targets cached for {} creeps", creep_targets.len(), game::creeps().values().count()));
+
+        let mut names = game::creeps().keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let Some(creep) = game::creeps().get(name.clone()) else {
+                continue;
+            };
+            match creep_targets.get(&name) {
+                Some(target) => lines.push(format!("{} ({:?}): {:?}", name, Role::of(&creep), target)),
+                None if creep.spawning() => lines.push(format!("{} ({:?}): spawning", name, Role::of(&creep))),
+                None => lines.push(format!("{} ({:?}): no target!", name, Role::of(&creep))),
+            }
+        }
+
+        for room in game::rooms().values() {
+            if !room.controller().is_some_and(|controller| controller.my()) {
+                continue;
+            }
+            for source in room.find(find::SOURCES, None) {
+                let mined = creep_targets.values().any(|target| matches!(target, CreepTarget::Harvest(id) if *id == source.id()));
+                if !mined {
+                    lines.push(format!("{}: source {} at {:?} has no miner!", room.name(), source.id(), source.pos()));
+                }
+            }
+        }
+    });
+    lines.join("\n")
+}
+
+// forget every creep's target so they all get reassigned from scratch next tick, for getting
+// out of a bad state from the console with `reset_targets()`; targets only live in the wasm
+// heap, so there's nothing in memory to clear along with them
+#[wasm_bindgen]
+pub fn reset_targets() {
+    CREEP_TARGETS.with(|creep_targets_refcell| {
+        let mut creep_targets = creep_targets_refcell.borrow_mut();
+        warn!("clearing {} creep targets", creep_targets.len());
+        creep_targets.clear();
+    });
+}
+
+// what to spend the CPU bucket on once it's full, rather than letting the extra go to waste;
+// set with `Memory.bucketSurplus` ("pixel", "planning", "market" or "nothing"), pixels by
+// default
+enum BucketSurplus {
+    // turn it into a pixel, if we're built with the `generate-pixel` feature
+    GeneratePixel,
+    // run the room planning passes (the layout, source containers and remote survey) every
+    // tick rather than only on a level change or their usual schedule
+    ExtraPlanning,
+    // keep sell orders priced every tick rather than every `schedule::Pass::Market`
+    ExtraMarket,
+    // leave it alone
+    Nothing,
+}
+
+impl BucketSurplus {
+    fn current() -> BucketSurplus {
+        match memory::get_string(&screeps::memory::ROOT, "bucketSurplus").as_deref() {
+            Some("pixel") | None => BucketSurplus::GeneratePixel,
+            Some("planning") => BucketSurplus::ExtraPlanning,
+            Some("market") => BucketSurplus::ExtraMarket,
+            Some("nothing") => BucketSurplus::Nothing,
+            Some(other) => {
+                warn!("unknown bucket surplus use {:?}, making pixels", other);
+                BucketSurplus::GeneratePixel
+            }
+        }
+    }
+}
+
 // this enum will represent a creep's lock on a specific target object, storing a js reference
 // to the object id so that we can grab a fresh reference to the object each successive tick,
 // since screeps game objects become 'stale' and shouldn't be used beyond the tick they were fetched
-#[derive(Clone)]
+//
+// position targets aren't required to be in the creep's current room - creeps head towards
+// them with `movement::travel_to`, which walks room by room along a route for anything that
+// isn't local
+#[derive(Clone, Debug, PartialEq)]
 enum CreepTarget {
-    Construct(Position),
+    // the site's position and what's being built there, so on completion we know what to
+    // look for at the spot
+    Construct(Position, StructureType),
     Pickup(Position),
     Repair(Position),
     Deposit(Position),
     Harvest(ObjectId<Source>),
     Upgrade(ObjectId<StructureController>),
-    Withdraw(ObjectId<StructureContainer>),
+    // with an amount when the creep only needs that much to top things off
+    Withdraw(ObjectId<StructureContainer>, Option<u32>),
+    Heal(ObjectId<Creep>),
+    Boost(ObjectId<StructureLab>),
+    EmergencyWithdraw(ObjectId<StructureSpawn>),
+    Attack(ObjectId<Creep>),
+    Guard(Position),
+    // bring a lab the mineral it's meant to hold, fetched from storage or the terminal
+    FillLab(ObjectId<StructureLab>, ResourceType),
+    // take what a lab has made (or shouldn't be holding) away to storage or the terminal
+    EmptyLab(ObjectId<StructureLab>),
+    // carry energy over from the storage to the terminal, for sending and selling
+    StockTerminal,
+    // put energy the terminal's been sent away in the storage
+    UnloadTerminal,
+    // keep a remote room's controller reserved
+    Reserve(ObjectId<StructureController>),
+    // keep the room's towers stocked from its energy buffer for as long as the fighting goes on
+    FeedTowers,
+    // walk to the spawn to be recycled, for creeps too badly damaged to be worth keeping
+    Recycle(ObjectId<StructureSpawn>),
+    // drop everything near the controller for the upgraders, for carriers with nowhere else
+    // to put their energy
+    Dump(Position),
+}
+
+// whether a creep can still act on its target, given what we can see this tick
+enum TargetCheck {
+    Act,
+    // the target is in a room we can't see, so there's nothing to look at until we get there
+    Travel(Position),
+    // the target's gone, or we've lost sight of it and have nowhere to go looking for it
+    Lost,
+}
+
+impl CreepTarget {
+    // where the target is, for the ones that are kept as a position
+    fn position(&self) -> Option<Position> {
+        match self {
+            CreepTarget::Construct(position, _) | CreepTarget::Pickup(position) | CreepTarget::Repair(position) | CreepTarget::Deposit(position) | CreepTarget::Guard(position) | CreepTarget::Dump(position) => Some(*position),
+            _ => None,
+        }
+    }
+
+    // where the creep has to be to act on the target, for targets it goes to rather than
+    // chasing; fighting and healing follow their target around instead, and drop it once it's
+    // left the room
+    fn site(&self) -> Option<Position> {
+        if let Some(position) = self.position() {
+            return Some(position);
+        }
+        match self {
+            CreepTarget::Harvest(id) => id.resolve().map(|source| source.pos()).or_else(|| remote::source_position(*id)),
+            CreepTarget::Upgrade(id) | CreepTarget::Reserve(id) => id.resolve().map(|controller| controller.pos()),
+            CreepTarget::Withdraw(id, _) => id.resolve().map(|container| container.pos()),
+            CreepTarget::Boost(id) | CreepTarget::FillLab(id, _) | CreepTarget::EmptyLab(id) => id.resolve().map(|lab| lab.pos()),
+            CreepTarget::EmergencyWithdraw(id) | CreepTarget::Recycle(id) => id.resolve().map(|spawn| spawn.pos()),
+            _ => None,
+        }
+    }
+
+    // position targets in rooms we can't see are walked towards, to be looked at properly on
+    // arrival; objects only ever resolve while their room is visible, so ones that don't are
+    // lost - apart from remote sources, whose position we remember
+    fn check(&self) -> TargetCheck {
+        if let Some(position) = self.position() {
+            return if game::rooms().get(position.room_name()).is_some() { TargetCheck::Act } else { TargetCheck::Travel(position) };
+        }
+        let resolves = match self {
+            CreepTarget::Harvest(id) => id.resolve().is_some() || remote::source_position(*id).is_some(),
+            CreepTarget::Upgrade(id) | CreepTarget::Reserve(id) => id.resolve().is_some(),
+            CreepTarget::Withdraw(id, _) => id.resolve().is_some(),
+            CreepTarget::Heal(id) | CreepTarget::Attack(id) => id.resolve().is_some(),
+            CreepTarget::Boost(id) | CreepTarget::FillLab(id, _) | CreepTarget::EmptyLab(id) => id.resolve().is_some(),
+            CreepTarget::EmergencyWithdraw(id) | CreepTarget::Recycle(id) => id.resolve().is_some(),
+            _ => true,
+        };
+        if resolves { TargetCheck::Act } else { TargetCheck::Lost }
+    }
+}
+
+// the creeps with an entry in `Memory.creeps` that aren't alive any more, going by its keys. on a
+// fresh account or after a memory wipe `Memory.creeps` may be missing or not an object at all
+// (`None`), in which case there's nothing to clean up, and a key that isn't a string (also
+// `None`) isn't a creep's
+fn dead_creeps(keys: Option<Vec<Option<String>>>, live_creeps: &HashSet<String>) -> Vec<String> {
+    keys.unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .filter(|creep_name| !live_creeps.contains(creep_name))
+        .collect()
 }
 
 // add wasm_bindgen to any function you would like to expose for call from js
 // to use a reserved name as a function name, use `js_name`:
-#[cfg(feature = "generate-pixel")]
 #[wasm_bindgen(js_name = loop)]
 pub fn game_loop() {
     INIT_LOGGING.call_once(|| {
@@ -51,164 +278,689 @@ pub fn game_loop() {
 
     debug!("loop starting! CPU: {}", game::cpu::get_used());
 
+    // names of every creep alive this tick, built once up front for everything that needs to
+    // check whether a creep is still around
+    let live_creeps: HashSet<String> = game::creeps().keys().collect();
+
+    remote::track_threats();
+    remote::track_reservations();
+
     // mutably borrow the creep_targets refcell, which is holding our creep target locks
     // in the wasm heap
     CREEP_TARGETS.with(|creep_targets_refcell| {
         let mut creep_targets = creep_targets_refcell.borrow_mut();
+        // forget about the targets of creeps that have died
+        creep_targets.retain(|name, _| live_creeps.contains(name));
+        expire_stale_targets(&mut creep_targets);
+        if game::time().is_multiple_of(population::UNNEEDED_CHECK_TICKS) {
+            retire_unneeded(&mut creep_targets);
+        }
         debug!("running creeps");
         for creep in game::creeps().values() {
+            // squad members and raiders are run together, further down
+            if squad::squad_of(&creep).is_some() || harass::raid_of(&creep).is_some() {
+                continue;
+            }
+            // remote creeps drop whatever they were doing when their room has hostiles in it
+            if remote::retreating(&creep) {
+                creep_targets.remove(&creep.name());
+                remote::retreat(&creep);
+                continue;
+            }
+            // early-game workers mining for themselves don't take targets
+            if shuttle::is_shuttle(&creep) {
+                creep_targets.remove(&creep.name());
+                shuttle::run_shuttle(&creep);
+                continue;
+            }
             run_creep(&creep, &mut creep_targets);
         }
-        assign_new_targets(&mut creep_targets);
+        assign_new_targets(&mut creep_targets, &live_creeps);
     });
+    squad::run_squad();
+    harass::run_raiders();
 
-    debug!("running towers");
-    for tower in game::structures().values() {
-        if let StructureObject::StructureTower(tower) = tower {
-            let available_energy = tower.store().get_used_capacity(Some(ResourceType::Energy));
-            if available_energy <= 100 {
-                //continue;
-            }
-
-            // Find the closest hostile creep
-            if let Some(target) = tower.pos().find_closest_by_range(find::HOSTILE_CREEPS) {
-                // Attack if in range
-                if tower.pos().in_range_to(target.pos(), 20) {
-                    tower.attack(&target);
-                    debug!("Tower attacking hostile creep at {:?}", target.pos());
-                }
-            } else {
-                // First, try to heal damaged creeps
-                if let Some(damaged_creep) = tower.pos().find_closest_by_range(find::MY_CREEPS)
-                    .filter(|creep| creep.hits() < creep.hits_max())
-                {
-                    tower.heal(&damaged_creep);
-                    debug!("Tower healing damaged creep at {:?}", damaged_creep.pos());
-                } else {
-                    // If no creeps need healing, prioritize repairing damaged structures
-                    let structures = tower.pos().find_in_range(find::STRUCTURES, 20);
-                    let structure = structures.iter().filter(|s| s.as_repairable().is_some() && s.as_structure().hits() < s.as_structure().hits_max()).min_by_key(|s| s.as_structure().hits());
-                    let rampart = structures.iter().filter(|s| matches!(s, StructureObject::StructureRampart(_)) && s.as_structure().hits() < s.as_structure().hits_max()).min_by_key(|s| s.as_structure().hits());
+    let bucket_full = game::cpu::bucket() == 10000;
+    let bucket_surplus = BucketSurplus::current();
+    let extra_planning = bucket_full && matches!(bucket_surplus, BucketSurplus::ExtraPlanning);
+    let extra_market = bucket_full && matches!(bucket_surplus, BucketSurplus::ExtraMarket);
 
-                    if let Some(rampart) = rampart {
-                        tower.repair(rampart.as_repairable().unwrap());
-                        debug!("Tower repairing damaged rampart at {:?}", rampart.pos());
-                    } else if let Some(structure) = structure {
-                        tower.repair(structure.as_repairable().unwrap());
-                        debug!("Tower repairing damaged structure at {:?}", structure.pos());
-                    }
-                }
-            }
+    for room in game::rooms().values() {
+        planner::check_controller_level(&room);
+        planner::rebuild_spawn(&room);
+        planner::plan_emergency_tower(&room);
+        links::run_links(&room);
+        labs::run_reactions(&room);
+        mining::track_sources(&room);
+        defense::track_siege(&room);
+        defense::track_controller_attack(&room);
+        defense::track_reinforcing(&room);
+        watchdog::check_economy(&room);
+        remote::observe(&room);
+        if extra_planning {
+            planner::replan(&room);
+        }
+        if schedule::due(schedule::Pass::RemoteSurvey, &room) || extra_planning {
+            remote::survey(&room);
+        }
+        if schedule::due(schedule::Pass::Containers, &room) || extra_planning {
+            planner::plan_containers(&room);
         }
+        if schedule::due(schedule::Pass::Market, &room) {
+            market::run_market(&room);
+        } else if extra_market {
+            market::keep_order_priced(&room);
+        }
+        terminal::run_terminal(&room);
     }
 
+    visuals::draw_roles();
+    visuals::draw_flows();
+
+    debug!("running towers");
+    defense::track_siphoners();
+    defense::track_drainers();
+    tower::run_towers();
+
     debug!("running spawns");
     let mut additional = 0;
     for spawn in game::spawns().values() {
         debug!("running spawn {}", String::from(spawn.name()));
 
+        // creeps about to die that should already have a replacement on the way don't count,
+        // so the last harvester or carrier gets replaced before there's a gap in the economy
         let harvesters = CREEP_TARGETS.with(|targets| {
             targets.borrow().iter()
-                .filter(|(name, target)| matches!(target, CreepTarget::Harvest(_)) && game::creeps().values().any(|c| c.name() == name.as_str()))
+                .filter(|(name, target)| matches!(target, CreepTarget::Harvest(_)) && live_creeps.contains(name.as_str()))
+                .filter(|(name, _)| game::creeps().get(name.to_string()).is_some_and(|creep| remote::remote_room(&creep).is_none() && !population::replacement_due(&creep)))
                 .count()
         });
         let transporters = game::creeps().values()
-            .filter(|creep| creep.body().iter().any(|body| matches!(body.part(), Part::Carry)))
+            .filter(|creep| Role::of(creep).carries() && remote::remote_room(creep).is_none() && !population::replacement_due(creep))
             .count();
-        let sources = spawn.room().unwrap().find(find::SOURCES_ACTIVE, None).len();
+        let hungry_source = CREEP_TARGETS.with(|targets| {
+            let targets = targets.borrow();
+            spawn.room().unwrap().find(find::SOURCES_ACTIVE, None)
+                .into_iter()
+                .find(|source| source_has_room(source, &targets))
+        });
         let energy_available = spawn.room().unwrap().energy_available();
         let energy_capacity = spawn.room().unwrap().energy_capacity_available();
-        let creep_count = game::creeps().values().count();
+        let creep_count = population::creep_count(&spawn.room().unwrap());
+        let max_creeps = population::max_creeps(&spawn.room().unwrap());
+        // harvesters in the room bring in roughly 2 energy per WORK part per tick
+        let income = CREEP_TARGETS.with(|targets| {
+            targets.borrow().iter()
+                .filter(|(_, target)| matches!(target, CreepTarget::Harvest(_)))
+                .filter_map(|(name, _)| game::creeps().get(name.clone()))
+                .filter(|creep| creep.room().map(|r| r.name()) == spawn.room().map(|r| r.name()))
+                .map(|creep| creep.get_active_bodyparts(Part::Work) as u32 * 2)
+                .sum::<u32>()
+        });
+        let tiers = if hungry_source.is_some() { &HARVESTER_TIERS } else { &CARRIER_TIERS };
+        note_tier_cap(&spawn.room().unwrap(), tiers);
+        let waiting = worth_waiting(energy_available, energy_capacity, tiers, income);
+        if waiting {
+            debug!("holding off spawning, {} energy will reach the next body tier soon", energy_available);
+        }
         let name_base = game::time();
         let name = format!("{}-{}", name_base, additional);
 
-        if (energy_available == energy_capacity || harvesters == 0 || transporters == 0) && creep_count < 6 {
-            if harvesters < sources {
-                match energy_available {
-                    300..=549 => {
-                        let body = [Part::Move, Part::Move, Part::Work, Part::Work];
-                        match spawn.spawn_creep(&body, &name) {
-                            Ok(()) => additional += 1,
-                            Err(e) => warn!("couldn't spawn: {:?}", e),
-                        }
-                    },
-                    550..=749 => {
-                        let body = [Part::Move, Part::Move, Part::Move, Part::Work, Part::Work, Part::Work, Part::Work];
-                        match spawn.spawn_creep(&body, &name) {
-                            Ok(()) => additional += 1,
-                            Err(e) => warn!("couldn't spawn: {:?}", e),
+        // no income for a long while means something is wrong with the harvesters, so stop
+        // waiting around for bigger bodies and get whatever we can afford out
+        let stalled = watchdog::is_stalled(&spawn.room().unwrap());
+
+        // how defenders and medics are weighed against the economy is up to the spawn strategy
+        // (someone going for the controller counts, even if they're not in the room right now)
+        let hostiles = spawn.room().unwrap().find(find::HOSTILE_CREEPS, None).len()
+            .max(defense::controller_contested(&spawn.room().unwrap()) as usize);
+        // (squad members and raiders are off fighting elsewhere, so they don't count)
+        let defenders = game::creeps().values()
+            .filter(|creep| Role::of(creep) == Role::Defender && squad::squad_of(creep).is_none() && harass::raid_of(creep).is_none())
+            .count();
+        let medics = game::creeps().values()
+            .filter(|creep| Role::of(creep) == Role::Medic && squad::squad_of(creep).is_none())
+            .count();
+
+        // losing the last harvester or carrier always gets it replaced, whatever the caps say
+        let economy_role = if hungry_source.is_some() { Role::Harvester } else { Role::Worker };
+        let critical = harvesters == 0 || transporters == 0;
+        let mode = strategy::SpawnMode::of(critical, stalled);
+        let room_for_more = creep_count < max_creeps
+            && population::role_count(&spawn.room().unwrap(), economy_role) < population::role_cap(&spawn.room().unwrap(), economy_role);
+        // growing the economy waits on the energy for a bigger body, replacing it doesn't
+        let economy = ((mode == strategy::SpawnMode::Replacement || !waiting) && (room_for_more || critical)).then_some(economy_role);
+        let state = strategy::RoomState { hostiles, defenders, medics, economy };
+        // anything that isn't replacing the economy or fighting off an attack leaves the spawn
+        // reserve alone
+        let held_back = |body: &[Part], urgent: bool| {
+            let cost = body.iter().map(|part| part.cost()).sum::<u32>();
+            let held = !urgent && strategy::eats_reserve(&spawn.room().unwrap(), cost);
+            if held {
+                info!("{}: not spawning a {} energy body, it'd cut into the spawn reserve", String::from(spawn.name()), cost);
+            }
+            held
+        };
+        let replacing = mode == strategy::SpawnMode::Replacement;
+        match strategy::spawn_order(strategy::SpawnStrategy::current(), &state).first() {
+            Some(Role::Defender) => {
+                let body = defender_body_for(&spawn, energy_available);
+                if !body.is_empty() && !held_back(&body, hostiles > 0) && spawn_creep(&spawn, Role::Defender, &body, &name) {
+                    additional += 1;
+                }
+            }
+            Some(Role::Medic) => {
+                let body = match energy_available {
+                    600.. => vec![Part::Move, Part::Move, Part::Heal, Part::Heal],
+                    300..=599 => vec![Part::Move, Part::Heal],
+                    _ => vec![],
+                };
+                if !body.is_empty() && !held_back(&body, hostiles > 0) && spawn_creep(&spawn, Role::Medic, &body, &name) {
+                    additional += 1;
+                }
+            }
+            Some(_) => {
+                if let Some(source) = hungry_source {
+                    if mining::open_tiles(&source) == 1 && energy_capacity >= 550 {
+                        // only one harvester fits next to this source, so wait for enough energy to
+                        // spawn one that saturates it on its own
+                        let body = [Part::Move, Part::Work, Part::Work, Part::Work, Part::Work, Part::Work];
+                        if energy_available >= 550 && !held_back(&body, replacing) && spawn_creep(&spawn, Role::Harvester, &body, &name) {
+                            additional += 1;
                         }
-                    },
-                    750.. => {
-                        let body = [Part::Move, Part::Move, Part::Move, Part::Move, Part::Move, Part::Work, Part::Work, Part::Work, Part::Work, Part::Work];
-                        match spawn.spawn_creep(&body, &name) {
-                            Ok(()) => additional += 1,
-                            Err(e) => warn!("couldn't spawn: {:?}", e),
+                    } else if energy_available >= 300 {
+                        // just big enough to make up what the source's miners are short of,
+                        // which `mining::track_sources` keeps an eye on from cycle to cycle
+                        let (_, work_parts) = CREEP_TARGETS.with(|targets| source_miners(&source, &targets.borrow()));
+                        let work = mining::work_parts_needed(&source).saturating_sub(work_parts).max(1);
+                        // with links at the sources, bring a CARRY part to fill them with
+                        let carry = energy_available >= 800 && links::has_source_links(&spawn.room().unwrap());
+                        let body = mining::harvester_body(energy_available, work, carry);
+                        if !body.is_empty() && !held_back(&body, replacing) && spawn_creep(&spawn, Role::Harvester, &body, &name) {
+                            additional += 1;
                         }
-                    },
-                    _ => {}
+                    }
+                } else {
+                    let body = match energy_available {
+                        300..=549 => vec![Part::Move, Part::Move, Part::Carry, Part::Carry, Part::Work],
+                        550..=799 => vec![Part::Move, Part::Move, Part::Move, Part::Carry, Part::Carry, Part::Carry, Part::Carry, Part::Work, Part::Work],
+                        800.. => vec![Part::Move, Part::Move, Part::Move, Part::Move, Part::Carry, Part::Carry, Part::Carry, Part::Carry, Part::Work, Part::Work, Part::Work, Part::Work],
+                        _ => vec![],
+                    };
+                    if !body.is_empty() && !held_back(&body, replacing) && spawn_creep(&spawn, Role::Worker, &body, &name) {
+                        additional += 1;
+                    }
                 }
-            } else {
-                match energy_available {
-                    300..=549 => {
-                        let body = [Part::Move, Part::Move, Part::Carry, Part::Carry, Part::Work];
-                        match spawn.spawn_creep(&body, &name) {
-                            Ok(()) => additional += 1,
-                            Err(e) => warn!("couldn't spawn: {:?}", e),
+            }
+            None if creep_count >= max_creeps && hostiles == 0 && !stalled && hungry_source.is_none() => {
+                // the home room has everything it needs, so fill out an attack squad if one's
+                // been called for (full size bodies only), then raiders if a raid on a
+                // neighbour is due, or else start working the rooms around it
+                let growth_energy = strategy::SpawnMode::Growth.body_energy(energy_available, energy_capacity);
+                let affordable = |body: &[Part]| !body.is_empty() && body.iter().map(|part| part.cost()).sum::<u32>() <= energy_available;
+                if let Some((role, squad_name)) = squad::needed_member() {
+                    let body = squad::body(role, growth_energy);
+                    if affordable(&body) && !held_back(&body, false) {
+                        let memory = role.memory();
+                        squad::tag_memory(&memory, squad_name);
+                        if spawn_creep_with_memory(&spawn, role, &body, &name, memory) {
+                            info!("spawning a squad {:?}", role);
+                            additional += 1;
                         }
-                    },
-                    550..=799 => {
-                        let body = [Part::Move, Part::Move, Part::Move, Part::Carry, Part::Carry, Part::Carry, Part::Carry, Part::Work, Part::Work];
-                        match spawn.spawn_creep(&body, &name) {
-                            Ok(()) => additional += 1,
-                            Err(e) => warn!("couldn't spawn: {:?}", e),
+                    }
+                } else if let Some((role, raid)) = harass::needed_raider(&spawn.room().unwrap()) {
+                    let body = squad::body(role, growth_energy);
+                    if affordable(&body) && !held_back(&body, false) {
+                        let memory = role.memory();
+                        harass::tag_memory(&memory, spawn.room().unwrap().name(), raid);
+                        if spawn_creep_with_memory(&spawn, role, &body, &name, memory) {
+                            info!("spawning a raiding {:?} for {}", role, raid);
+                            additional += 1;
                         }
-                    },
-                    800.. => {
-                        let body = [Part::Move, Part::Move, Part::Move, Part::Move, Part::Carry, Part::Carry, Part::Carry, Part::Carry, Part::Work, Part::Work, Part::Work, Part::Work];
-                        match spawn.spawn_creep(&body, &name) {
-                            Ok(()) => additional += 1,
-                            Err(e) => warn!("couldn't spawn: {:?}", e),
+                    }
+                } else if let Some((remote, role)) = remote::needed_creep(&spawn.room().unwrap()) {
+                    let body = remote::body(role, growth_energy);
+                    if affordable(&body) && !held_back(&body, false) {
+                        let memory = role.memory();
+                        remote::tag_memory(&memory, spawn.room().unwrap().name(), remote);
+                        if spawn_creep_with_memory(&spawn, role, &body, &name, memory) {
+                            info!("spawning a remote {:?} for {}", role, remote);
+                            additional += 1;
                         }
-                    },
-                    _ => {}
+                    }
                 }
             }
+            None => {}
         }
     }
 
     // this should be removed if you're using RawMemory/serde for persistence
-    if game::time() % 10 == 0 {
+    if game::time().is_multiple_of(10) {
         info!("running memory cleanup");
-        let mut alive_creeps = HashSet::new();
-        for creep_name in game::creeps().keys() {
-            alive_creeps.insert(creep_name);
-        }
-
-        if let Ok(memory_creeps) = Reflect::get(&screeps::memory::ROOT, &JsString::from("creeps")) {
-            let memory_creeps: Object = memory_creeps.unchecked_into();
-            for creep_name_js in Object::keys(&memory_creeps).iter() {
-                let creep_name = String::from(creep_name_js.dyn_ref::<JsString>().unwrap());
-
-                if !alive_creeps.contains(&creep_name) {
+        let memory_creeps = Reflect::get(&screeps::memory::ROOT, &JsString::from("creeps"))
+            .ok()
+            .filter(|memory_creeps| memory_creeps.is_object())
+            .map(|memory_creeps| memory_creeps.unchecked_into::<Object>());
+        let keys = memory_creeps.as_ref().map(|memory_creeps| Object::keys(memory_creeps).iter().map(|key| key.as_string()).collect());
+        match memory_creeps {
+            Some(memory_creeps) => {
+                for creep_name in dead_creeps(keys, &live_creeps) {
                     info!("deleting memory for dead creep {}", creep_name);
-                    let _ = Reflect::delete_property(&memory_creeps, &creep_name_js);
+                    let _ = Reflect::delete_property(&memory_creeps, &JsString::from(creep_name));
                 }
             }
+            None => debug!("no creep memory to clean up"),
         }
     }
 
-    if game::cpu::bucket() == 10000 {
-        let _ = game::cpu::generate_pixel();
+    stats::record_stats();
+
+    if bucket_full {
+        match bucket_surplus {
+            BucketSurplus::GeneratePixel => {
+                #[cfg(feature = "generate-pixel")]
+                if pixels_supported() {
+                    generate_pixel();
+                }
+            }
+            // already spent on planning and the market above
+            BucketSurplus::ExtraPlanning | BucketSurplus::ExtraMarket => {}
+            BucketSurplus::Nothing => {}
+        }
     }
 
     if !game::cpu::unlocked() {
-        let _ = game::cpu::unlock();
+        unlock_cpu();
     }
 
     info!("done! cpu: {}", game::cpu::get_used())
 }
 
+// whether this server turns CPU into pixels at all; only the official servers do, and private
+// ones either don't have `Game.cpu.generatePixel` or fail every call to it. worked out on the
+// first full bucket and kept as `Memory.pixels`, which can be deleted to have it checked again
+#[cfg(feature = "generate-pixel")]
+fn pixels_supported() -> bool {
+    if let Some(pixels) = memory::get_u32(&screeps::memory::ROOT, "pixels") {
+        return pixels > 0;
+    }
+    let cpu = Reflect::get(&js_sys::global(), &JsString::from("Game")).and_then(|game| Reflect::get(&game, &JsString::from("cpu")));
+    let available = cpu.is_ok_and(|cpu| Reflect::get(&cpu, &JsString::from("generatePixel")).is_ok_and(|generate| generate.is_function()));
+    if !available {
+        warn!("this server doesn't generate pixels, leaving the bucket alone");
+        memory::set_u32(&screeps::memory::ROOT, "pixels", 0);
+    }
+    available
+}
+
+// turn the full bucket into a pixel, backing off after failures rather than trying (and
+// logging it) again every tick
+#[cfg(feature = "generate-pixel")]
+fn generate_pixel() {
+    PIXEL_BACKOFF.with(|backoff_refcell| {
+        let mut backoff = backoff_refcell.borrow_mut();
+        if game::time() < backoff.0 {
+            return;
+        }
+        match game::cpu::generate_pixel() {
+            Ok(()) => {
+                info!("generated a pixel");
+                memory::set_u32(&screeps::memory::ROOT, "pixels", 1);
+                *backoff = (0, PIXEL_RETRY_TICKS);
+            }
+            Err(e) => {
+                warn!("couldn't generate a pixel: {:?}, trying again in {} ticks", e, backoff.1);
+                *backoff = (game::time() + backoff.1, (backoff.1 * 2).min(PIXEL_RETRY_MAX_TICKS));
+            }
+        }
+    });
+}
+
+// spend a cpu unlock if we've got one, backing off after failures rather than trying (and
+// failing) again every tick; with no unlocks left at all there's no point trying again soon
+fn unlock_cpu() {
+    UNLOCK_BACKOFF.with(|backoff_refcell| {
+        let mut backoff = backoff_refcell.borrow_mut();
+        if game::time() < backoff.0 {
+            return;
+        }
+        match game::cpu::unlock() {
+            Ok(()) => {
+                warn!("unlocked the cpu");
+                *backoff = (0, UNLOCK_RETRY_TICKS);
+            }
+            Err(ErrorCode::NotEnough) => {
+                debug!("no cpu unlocks to spend, checking again in {} ticks", UNLOCK_RETRY_MAX_TICKS);
+                *backoff = (game::time() + UNLOCK_RETRY_MAX_TICKS, UNLOCK_RETRY_TICKS);
+            }
+            Err(e) => {
+                warn!("couldn't unlock the cpu: {:?}, trying again in {} ticks", e, backoff.1);
+                *backoff = (game::time() + backoff.1, (backoff.1 * 2).min(UNLOCK_RETRY_MAX_TICKS));
+            }
+        }
+    });
+}
+
+// the energy needed for each step up in body size, matching the bodies in `game_loop`
+const HARVESTER_TIERS: [u32; 3] = [300, 550, 750];
+const CARRIER_TIERS: [u32; 3] = [300, 550, 800];
+
+// the body tiers the room's spawn and extensions can hold the energy for at all; bigger ones
+// are never waited on, however long the room sits full
+fn achievable_tiers(tiers: &[u32], energy_capacity: u32) -> impl Iterator<Item = u32> + '_ {
+    tiers.iter().copied().filter(move |&tier| tier <= energy_capacity)
+}
+
+// log when the room's energy capacity is what stops it building the biggest bodies, whenever
+// that changes; the tier it's capped at is kept as `Memory.rooms[name].tierCap` (0 when it
+// can't pay for even the smallest), and left out when nothing's capped
+fn note_tier_cap(room: &Room, tiers: &[u32]) {
+    let capacity = room.energy_capacity_available();
+    let cap = tiers.last()
+        .is_some_and(|&last| last > capacity)
+        .then(|| achievable_tiers(tiers, capacity).max().unwrap_or(0));
+    let room_memory = memory::room(room.name());
+    if memory::get_u32(&room_memory, "tierCap") == cap {
+        return;
+    }
+    match cap {
+        Some(0) => info!("{}: {} energy capacity can't pay for even the smallest body tier", room.name(), capacity),
+        Some(cap) => info!("{}: {} energy capacity limits new bodies to the {} energy tier", room.name(), capacity, cap),
+        None => info!("{}: {} energy capacity pays for every body tier", room.name(), capacity),
+    }
+    match cap {
+        Some(cap) => memory::set_u32(&room_memory, "tierCap", cap),
+        None => {
+            let _ = Reflect::delete_property(&room_memory, &JsString::from("tierCap"));
+        }
+    }
+}
+
+// how many ticks we're willing to hold off a spawn to get a bigger body out of it
+const SPAWN_WAIT_TICKS: u32 = 20;
+
+// whether to hold off spawning because, at the current income, energy will climb to the next
+// body tier the room can afford within `SPAWN_WAIT_TICKS`; once the biggest affordable tier is
+// reached there's nothing to wait for, and without any income to estimate from we just wait
+// for the room to fill up like before
+fn worth_waiting(energy_available: u32, energy_capacity: u32, tiers: &[u32], income: u32) -> bool {
+    let next_tier = achievable_tiers(tiers, energy_capacity).find(|&tier| tier > energy_available);
+    match next_tier {
+        Some(tier) if income > 0 => (tier - energy_available).div_ceil(income) <= SPAWN_WAIT_TICKS,
+        Some(_) => true,
+        None => false,
+    }
+}
+
+const ALL_DIRECTIONS: [Direction; 8] = [
+    Direction::Top,
+    Direction::TopRight,
+    Direction::Right,
+    Direction::BottomRight,
+    Direction::Bottom,
+    Direction::BottomLeft,
+    Direction::Left,
+    Direction::TopLeft,
+];
+
+// pick the directions a new creep is allowed to step out of the spawn towards - open tiles
+// first, so it doesn't pop out onto a road and block traffic, falling back to roads if
+// that's all there is around the spawn
+fn spawn_directions(spawn: &StructureSpawn) -> Vec<Direction> {
+    let room = spawn.room().expect("couldn't resolve spawn room");
+    let walkable = movement::walkable_neighbors(spawn.pos(), &room);
+    let mut open = Vec::new();
+    let mut roads = Vec::new();
+    for direction in ALL_DIRECTIONS {
+        if let Ok(pos) = spawn.pos().checked_add_direction(direction) {
+            if !walkable.contains(&pos) {
+                continue;
+            }
+            let structures = pos.look_for(look::STRUCTURES).unwrap_or_default();
+            if structures.iter().any(|s| matches!(s, StructureObject::StructureRoad(_))) {
+                roads.push(direction);
+            } else {
+                open.push(direction);
+            }
+        }
+    }
+    if open.is_empty() {
+        roads
+    } else {
+        open
+    }
+}
+
+// drop any target a creep has been holding for more than `TARGET_TTL_TICKS`, bar the ones held
+// for good: harvesting a source, upgrading (which refills from the controller's supply and
+// carries on), reserving, guarding a rampart and feeding towers through a fight
+fn expire_stale_targets(creep_targets: &mut HashMap<String, CreepTarget>) {
+    let now = game::time();
+    TARGET_AGES.with(|ages_refcell| {
+        let mut ages = ages_refcell.borrow_mut();
+        ages.retain(|name, _| creep_targets.contains_key(name));
+        for (name, target) in creep_targets.iter() {
+            match ages.get(name) {
+                Some((held, _)) if held == target => {}
+                _ => {
+                    ages.insert(name.clone(), (target.clone(), now));
+                }
+            }
+        }
+        let stale = ages.iter()
+            .filter(|(_, (target, since))| !matches!(target, CreepTarget::Harvest(_) | CreepTarget::Upgrade(_) | CreepTarget::Reserve(_) | CreepTarget::Guard(_) | CreepTarget::FeedTowers) && now - since > TARGET_TTL_TICKS)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in stale {
+            if let Some((target, since)) = ages.remove(&name) {
+                warn!("{}: still on {:?} after {} ticks, dropping it", name, target, now - since);
+                creep_targets.remove(&name);
+            }
+        }
+    });
+}
+
+// withdraw up to `amount` of a resource from `from`, walking over to it first if need be
+fn fetch<T>(creep: &Creep, from: &T, resource: ResourceType, amount: u32) -> Result<(), ErrorCode>
+where
+    T: Withdrawable + HasStore,
+{
+    if !creep.pos().is_near_to(from.pos()) {
+        return movement::move_to(creep, from.pos());
+    }
+    let amount = amount.min(from.store().get_used_capacity(Some(resource)));
+    intents::act(creep, || creep.withdraw(from, resource, Some(amount)))
+}
+
+// whether the resource has been handed over, or the creep is still on its way
+fn stash<T>(creep: &Creep, to: &T, resource: ResourceType) -> Result<bool, ErrorCode>
+where
+    T: Transferable + HasStore,
+{
+    if !creep.pos().is_near_to(to.pos()) {
+        return movement::move_to(creep, to.pos()).map(|_| false);
+    }
+    intents::act(creep, || creep.transfer(to, resource, None)).map(|_| true)
+}
+
+// spawn a creep with its role encoded in front of `name` (see `Role::from_name`)
+fn spawn_creep(spawn: &StructureSpawn, role: Role, body: &[Part], name: &str) -> bool {
+    spawn_creep_with_memory(spawn, role, body, name, role.memory())
+}
+
+// whether the creep was spawned. the spawn is tried as a dry run first, which checks the body
+// and name and whether the spawn's free and can afford it without spending anything, so the
+// everyday reasons not to spawn (still busy with the last creep, or short of energy) don't get
+// logged as failures; anything else wrong with the spawn does
+fn spawn_creep_with_memory(spawn: &StructureSpawn, role: Role, body: &[Part], name: &str, memory: Object) -> bool {
+    let body = &clamp_body(role, body);
+    // every creep remembers the room it was spawned for, remote creeps and raiders having
+    // already been given theirs
+    if memory::get_string(&memory, "home").is_none() {
+        memory::set_string(&memory, "home", &spawn.pos().room_name().to_string());
+    }
+    let name = format!("{}{}", role.prefix(), name);
+    let name = name.as_str();
+    match spawn.spawn_creep_with_options(body, name, &SpawnOptions::new().dry_run(true)) {
+        Ok(()) => {}
+        Err(ErrorCode::Busy) | Err(ErrorCode::NotEnough) => {
+            debug!("{}: can't spawn a {:?} right now", String::from(spawn.name()), role);
+            return false;
+        }
+        Err(e) => {
+            warn!("{}: won't be able to spawn a {:?} with {:?}: {:?}", String::from(spawn.name()), role, body, e);
+            return false;
+        }
+    }
+    let directions = spawn_directions(spawn);
+    let options = SpawnOptions::new().memory(memory.into());
+    let result = if directions.is_empty() {
+        spawn.spawn_creep_with_options(body, name, &options)
+    } else {
+        spawn.spawn_creep_with_options(body, name, &options.directions(&directions))
+    };
+    if let Err(e) = &result {
+        warn!("{}: couldn't spawn a {:?}: {:?}", String::from(spawn.name()), role, e);
+    }
+    result.is_ok()
+}
+
+// cut a body with more than the 50 parts a creep can have down to size, keeping each kind of
+// part in about the same proportion (and at least one of each), with whatever room that
+// leaves going to the parts the role needs most; bodies that fit are left alone
+fn clamp_body(role: Role, body: &[Part]) -> Vec<Part> {
+    let max = MAX_CREEP_SIZE as usize;
+    if body.len() <= max {
+        return body.to_vec();
+    }
+    // least important first
+    let mut kinds = Vec::new();
+    for part in body {
+        if !kinds.contains(part) {
+            kinds.push(*part);
+        }
+    }
+    let priorities = role.part_priority();
+    kinds.sort_by_key(|part| std::cmp::Reverse(priorities.iter().position(|p| p == part).unwrap_or(priorities.len())));
+    let count = |part: Part| body.iter().filter(|p| **p == part).count();
+    let mut keep = kinds.iter().map(|&part| (part, (count(part) * max / body.len()).max(1))).collect::<Vec<_>>();
+    while keep.iter().map(|(_, n)| n).sum::<usize>() > max {
+        if let Some((_, n)) = keep.iter_mut().find(|(_, n)| *n > 1) {
+            *n -= 1;
+        } else {
+            break;
+        }
+    }
+    while keep.iter().map(|(_, n)| n).sum::<usize>() < max {
+        match keep.iter_mut().rev().find(|(part, n)| *n < count(*part)) {
+            Some((_, n)) => *n += 1,
+            None => break,
+        }
+    }
+    // in the body's own order, so whatever's meant to soak up damage first still does
+    let mut clamped = Vec::with_capacity(max);
+    for part in body {
+        if let Some((_, n)) = keep.iter_mut().find(|(kind, n)| kind == part && *n > 0) {
+            *n -= 1;
+            clamped.push(*part);
+        }
+    }
+    info!("cut a {} part {} body down to {}: {:?}", body.len(), role.as_str(), clamped.len(), clamped);
+    clamped
+}
+
+// a creep with this few ticks left to live puts whatever energy it's holding away, so it isn't
+// dropped (and left to decay) when the creep dies
+const LAST_JOB_TICKS: u32 = 30;
+
+// send creeps nothing needs any more off to be recycled, if that's turned on; otherwise
+// `population::track_unneeded` just logs them
+fn retire_unneeded(creep_targets: &mut HashMap<String, CreepTarget>) {
+    for room in game::rooms().values() {
+        if !room.controller().is_some_and(|controller| controller.my()) {
+            continue;
+        }
+        for creep in population::track_unneeded(&room) {
+            if !population::recycle_unneeded() || matches!(creep_targets.get(&creep.name()), Some(CreepTarget::Recycle(_))) {
+                continue;
+            }
+            // a creep that's wandered off is walked back to one of its own room's spawns
+            let spawn = creep.pos().find_closest_by_range(find::MY_SPAWNS)
+                .or_else(|| room.find(find::MY_SPAWNS, None).into_iter().next());
+            if let Some(spawn) = spawn {
+                warn!("{}: recycling it, nothing needs a {}", creep.name(), population::built_for(&creep).as_str());
+                creep_targets.insert(creep.name(), CreepTarget::Recycle(spawn.id()));
+            }
+        }
+    }
+}
+
+// whether creeps with nothing to harvest get killed off, as they used to be; the default is to
+// leave them parked until a source regenerates, and only get rid of creeps without a working
+// WORK part. `Memory.allowSuicide = 1` turns it on
+fn allow_suicide() -> bool {
+    memory::get_u32(&screeps::memory::ROOT, "allowSuicide").is_some_and(|allow| allow > 0)
+}
+
+// where a creep on `target` is taking its energy from, which isn't somewhere to put it back
+fn withdrawn_from(creep: &Creep, target: &CreepTarget) -> Option<Position> {
+    match target {
+        CreepTarget::Withdraw(id, _) => id.resolve().map(|container| container.pos()),
+        CreepTarget::EmergencyWithdraw(id) => id.resolve().map(|spawn| spawn.pos()),
+        CreepTarget::StockTerminal => creep.room()?.storage().map(|storage| storage.pos()),
+        CreepTarget::UnloadTerminal => creep.room()?.terminal().map(|terminal| terminal.pos()),
+        _ => None,
+    }
+}
+
+// the last job of a creep about to die, overriding whatever else it was doing: walk the energy
+// it's holding to the nearest structure that'll take it, if it can get there in time. it's left
+// alone if what it was doing already uses its energy up. returns whether it's busy doing so
+fn put_away_before_dying(creep: &Creep, target: Option<&CreepTarget>) -> bool {
+    let energy = creep.store().get_used_capacity(Some(ResourceType::Energy));
+    let Some(ticks_to_live) = creep.ticks_to_live() else {
+        return false;
+    };
+    if energy == 0 || ticks_to_live > LAST_JOB_TICKS {
+        return false;
+    }
+    if matches!(
+        target,
+        Some(CreepTarget::Upgrade(_) | CreepTarget::Construct(..) | CreepTarget::Repair(_) | CreepTarget::Deposit(_) | CreepTarget::Dump(_) | CreepTarget::FeedTowers)
+    ) {
+        return false;
+    }
+    let Some(room) = creep.room() else {
+        return false;
+    };
+    let source = target.and_then(|target| withdrawn_from(creep, target));
+    let nearest = room.find(find::STRUCTURES, None)
+        .into_iter()
+        .filter(|s| Some(s.pos()) != source)
+        .filter(|s| matches!(
+            s,
+            StructureObject::StructureSpawn(_) | StructureObject::StructureExtension(_) | StructureObject::StructureTower(_) | StructureObject::StructureStorage(_)
+                | StructureObject::StructureTerminal(_) | StructureObject::StructureLink(_) | StructureObject::StructureContainer(_)
+        ))
+        .filter(|s| s.as_owned().is_none_or(|owned| owned.my()))
+        .filter(|s| s.as_has_store().is_some_and(|store| store.store().get_free_capacity(Some(ResourceType::Energy)) > 0))
+        .min_by_key(|s| creep.pos().get_range_to(s.pos()));
+    let Some(structure) = nearest.filter(|s| creep.pos().get_range_to(s.pos()) <= ticks_to_live) else {
+        return false;
+    };
+    info!("{}: {} ticks left, putting its {} energy away", creep.name(), ticks_to_live, energy);
+    if creep.pos().is_near_to(structure.pos()) {
+        if let Some(transferable) = structure.as_transferable() {
+            if let Err(e) = intents::act(creep, || creep.transfer(transferable, ResourceType::Energy, None)) {
+                warn!("{}: couldn't put energy away: {:?}", creep.name(), e);
+            }
+        }
+    } else {
+        let _ = movement::move_to(creep, structure.pos());
+    }
+    true
+}
+
 fn run_creep(creep: &Creep, creep_targets: &mut HashMap<String, CreepTarget>) {
     if creep.spawning() {
         return;
@@ -216,18 +968,42 @@ fn run_creep(creep: &Creep, creep_targets: &mut HashMap<String, CreepTarget>) {
     let name = creep.name();
     debug!("running creep {}", name);
 
+    if put_away_before_dying(creep, creep_targets.get(&name)) {
+        return;
+    }
+
     if let Some(creep_target) = creep_targets.get(&name) {
+        match creep_target.check() {
+            TargetCheck::Act => {}
+            TargetCheck::Travel(position) => {
+                debug!("{}: can't see {} yet, heading over", name, position.room_name());
+                let _ = movement::travel_to(creep, position);
+                return;
+            }
+            TargetCheck::Lost => {
+                info!("{}: lost track of its target {:?}, clearing", name, creep_target);
+                creep_targets.remove(&name);
+                return;
+            }
+        }
+        // everything below works within the creep's own room, so a target in another room is
+        // travelled to first, room by room
+        if let Some(site) = creep_target.site().filter(|site| site.room_name() != creep.pos().room_name()) {
+            debug!("{}: target is over in {}, heading there", name, site.room_name());
+            let _ = movement::travel_to(creep, site);
+            return;
+        }
         match creep_target {
             CreepTarget::Upgrade(controller_id)
                 if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 =>
             {
                 info!("{}: upgrading", name);
                 if let Some(controller) = controller_id.resolve() {
-                    creep
-                        .upgrade_controller(&controller)
+                    intents::act(creep, || creep.upgrade_controller(&controller))
                         .unwrap_or_else(|e| match e {
                             ErrorCode::NotInRange => {
-                                let _ = creep.move_to(&controller);
+                                let _ = movement::move_to(creep, &controller);
+                                repair_in_passing(creep);
                             }
                             _ => {
                                 warn!("couldn't upgrade: {:?}", e);
@@ -239,38 +1015,78 @@ fn run_creep(creep: &Creep, creep_targets: &mut HashMap<String, CreepTarget>) {
                 }
             }
 
-            CreepTarget::Construct(position)
+            CreepTarget::Upgrade(controller_id) => {
+                // out of energy - upgraders refill from whatever supplies the controller. at a
+                // controller link they wait for it to be topped up rather than giving up their
+                // spot when it's empty, but an empty container could be a while, so they go
+                // and find energy elsewhere
+                match controller_id.resolve().map(|controller| upgrading::controller_supply(&controller)) {
+                    Some(upgrading::ControllerSupply::Link(link)) => {
+                        if link.store().get_used_capacity(Some(ResourceType::Energy)) == 0 {
+                            debug!("{}: waiting on controller link", name);
+                        } else if creep.pos().is_near_to(link.pos()) {
+                            info!("{}: withdrawing from controller link", name);
+                            let _ = intents::act(creep, || creep.withdraw(&link, ResourceType::Energy, None));
+                        } else {
+                            let _ = movement::move_to(creep, &link);
+                        }
+                    }
+                    Some(upgrading::ControllerSupply::Container(container)) if container.store().get_used_capacity(Some(ResourceType::Energy)) > 0 => {
+                        if creep.pos().is_near_to(container.pos()) {
+                            info!("{}: withdrawing from controller container", name);
+                            let _ = intents::act(creep, || creep.withdraw(&container, ResourceType::Energy, None));
+                        } else {
+                            let _ = movement::move_to(creep, &container);
+                        }
+                    }
+                    _ => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+
+            CreepTarget::Construct(position, structure_type)
                 if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 =>
             {
                 info!("{}: constructing", name);
+                let structure_type = *structure_type;
                 if creep.pos().is_near_to(*position) {
-                    if let Ok(results) = position.look_for(look::CONSTRUCTION_SITES) {
-                        if let Some(site) = results.first() {
-                            creep.build(&site).unwrap_or_else(|e| {
+                    match sight(position.look_for(look::CONSTRUCTION_SITES), |site| site.structure_type() == structure_type) {
+                        Sighting::Found(site) => {
+                            intents::act(creep, || creep.build(&site)).unwrap_or_else(|_| {
                                 creep_targets.remove(&name);
                             });
-                        } else {
-                            if let Ok(ramparts) = position.look_for(look::STRUCTURES) {
-                                if let Some(rampart) = ramparts.iter().find(|s| matches!(s, StructureObject::StructureRampart(_))) {
-                                    if let StructureObject::StructureRampart(rampart) = rampart {
-                                        creep.repair(rampart).unwrap_or_else(|e| {
-                                            creep_targets.remove(&name);
-                                        });
-                                    } else {
+                        }
+                        Sighting::Gone => {
+                            // the site is gone, either because it's been built or because it was
+                            // removed; only the structure we were building counts as built (there
+                            // may be others on the tile already, like a rampart over the top)
+                            let structures = position.look_for(look::STRUCTURES).unwrap_or_default();
+                            let built = structures.iter().find(|s| s.as_structure().structure_type() == structure_type);
+                            match built {
+                                // a freshly built rampart starts out with next to no hits and
+                                // decays away almost immediately, so stick around to build up its
+                                // hits before moving on
+                                Some(StructureObject::StructureRampart(rampart)) if rampart.hits() < FRESH_RAMPART_HITS => {
+                                    intents::act(creep, || creep.repair(rampart)).unwrap_or_else(|_| {
                                         creep_targets.remove(&name);
-                                    }
-                                } else {
+                                    });
+                                }
+                                Some(_) => {
+                                    info!("{}: finished building {:?} at {}", name, structure_type, position);
+                                    creep_targets.remove(&name);
+                                }
+                                None => {
+                                    info!("{}: {:?} construction site at {} is gone", name, structure_type, position);
                                     creep_targets.remove(&name);
                                 }
-                            } else {
-                                creep_targets.remove(&name);
                             }
                         }
-                    } else {
-                        creep_targets.remove(&name);
+                        Sighting::OutOfSight => out_of_sight(&name, *position),
                     }
                 } else {
-                    let _ = creep.move_to(*position);
+                    let _ = movement::travel_to(creep, *position);
+                    repair_in_passing(creep);
                 }
             }
 
@@ -279,86 +1095,179 @@ fn run_creep(creep: &Creep, creep_targets: &mut HashMap<String, CreepTarget>) {
                 info!("{}: harvesting", name);
                 if let Some(source) = source_id.resolve() {
                     if creep.pos().is_near_to(source.pos()) {
-                        let containers = source.pos().find_in_range(find::STRUCTURES, 1);
-                        if let Some(container) = containers.iter().find(|&s| matches!(s, StructureObject::StructureContainer(_))) {
+                        // with more than one harvester on a source only one of them gets to stand on the
+                        // container, the rest mine from wherever they are
+                        let container = mining::source_container(&source);
+                        let container = container.filter(|container| {
+                            creep.pos() == container.pos() || container.pos().look_for(look::CREEPS).map_or(true, |creeps| creeps.is_empty())
+                        });
+                        if let Some(container) = container {
                             if creep.pos() != container.pos() {
-                                let _ = creep.move_to(container.pos());
+                                let _ = movement::move_to(creep, container.pos());
+                            } else if let Some(repairable) = mining::container_needing_repair(creep, &container) {
+                                // a miner that can hold energy keeps its own container up, rather
+                                // than waiting on someone to come out and repair it
+                                if let Err(e) = intents::act(creep, || creep.repair(repairable)) {
+                                    warn!("couldn't repair container: {:?}", e);
+                                }
                             } else {
-                                creep.harvest(&source).unwrap_or_else(|e| {
+                                visuals::note_flow(source.pos(), container.pos(), creep.get_active_bodyparts(Part::Work) as u32 * HARVEST_POWER);
+                                intents::act(creep, || creep.harvest(&source)).unwrap_or_else(|_| {
                                     creep_targets.remove(&name);
                                 });
                             }
                         } else {
-                            creep.harvest(&source).unwrap_or_else(|e| {
+                            intents::act(creep, || creep.harvest(&source)).unwrap_or_else(|_| {
                                 creep_targets.remove(&name);
                             });
                         }
+                        links::fill_source_link(creep, &source);
                     } else {
-                        let _ = creep.move_to(&source);
+                        let _ = movement::travel_to(creep, source.pos());
                     }
+                } else if let Some(position) = remote::source_position(*source_id) {
+                    // a remote source we can't see right now, head for where it was
+                    let _ = movement::travel_to(creep, position);
                 } else {
                     creep_targets.remove(&name);
                 }
             }
 
-            CreepTarget::Withdraw(structure_id)
+            CreepTarget::Withdraw(structure_id, amount)
                 if creep.store().get_free_capacity(Some(ResourceType::Energy)) > 0 =>
             {
                 info!("{}: withdrawing", name);
                 if let Some(structure) = structure_id.resolve() {
                     if creep.pos().is_near_to(structure.pos()) {
-                        creep.withdraw(&structure, ResourceType::Energy, None).unwrap_or_else(|e| {
-                            creep_targets.remove(&name);
+                        // never ask for more than there's room for or more than the container has
+                        let amount = amount.map(|amount| {
+                            amount
+                                .min(creep.store().get_free_capacity(Some(ResourceType::Energy)) as u32)
+                                .min(structure.store().get_used_capacity(Some(ResourceType::Energy)))
                         });
+                        visuals::note_pickup(&name, structure.pos());
+                        let emptied = amount.is_none_or(|amount| amount >= structure.store().get_used_capacity(Some(ResourceType::Energy)))
+                            && structure.store().get_used_capacity(Some(ResourceType::Energy)) as i32 <= creep.store().get_free_capacity(Some(ResourceType::Energy));
+                        match intents::act(creep, || creep.withdraw(&structure, ResourceType::Energy, amount)) {
+                            // a container this takes the last of can't fill the creep any more, so
+                            // it moves straight on (to the next container on a remote route, say)
+                            Ok(()) if emptied => {
+                                creep_targets.remove(&name);
+                            }
+                            Ok(()) => {}
+                            Err(_) => {
+                                creep_targets.remove(&name);
+                            }
+                        }
                     } else {
-                        let _ = creep.move_to(&structure);
+                        let _ = movement::travel_to(creep, structure.pos());
                     }
                 } else {
                     creep_targets.remove(&name);
                 }
             }
-            CreepTarget::Pickup(position)
-                if creep.store().get_free_capacity(Some(ResourceType::Energy)) > 0 =>
+            CreepTarget::EmergencyWithdraw(spawn_id)
+                if creep.store().get_used_capacity(Some(ResourceType::Energy)) == 0 =>
             {
-                info!("{}: picking", name);
-                match position.look_for(look::ENERGY) {
-                    Ok(resources) => {
-                        if let Some(resource) = resources.first() {
-                            if creep.pos().is_near_to(*position) {
-                                creep.pickup(resource).unwrap_or_else(|e| {
+                info!("{}: emergency withdrawing", name);
+                match spawn_id.resolve() {
+                    // re-check on arrival, if the spawn has started on a creep it needs the energy
+                    Some(spawn) if spawn.spawning().is_none() => {
+                        if creep.pos().is_near_to(spawn.pos()) {
+                            let amount = EMERGENCY_WITHDRAW_AMOUNT.min(creep.store().get_free_capacity(Some(ResourceType::Energy)) as u32);
+                            match intents::act(creep, || creep.withdraw(&spawn, ResourceType::Energy, Some(amount))) {
+                                // straight to the controller with it, it would only get deposited
+                                // back into the spawn otherwise
+                                Ok(()) => match creep.room().and_then(|room| room.controller()) {
+                                    Some(controller) => {
+                                        creep_targets.insert(name, CreepTarget::Upgrade(controller.id()));
+                                    }
+                                    None => {
+                                        creep_targets.remove(&name);
+                                    }
+                                },
+                                Err(_) => {
                                     creep_targets.remove(&name);
-                                });
-                            } else {
-                                let _ = creep.move_to(*position);
+                                }
                             }
                         } else {
-                            creep_targets.remove(&name);
+                            let _ = movement::move_to(creep, &spawn);
                         }
-                    },
-                    Err(e) => {
+                    }
+                    _ => {
                         creep_targets.remove(&name);
                     }
                 }
             }
-            CreepTarget::Deposit(position)
-                if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 =>
+            CreepTarget::Pickup(position)
+                if creep.store().get_free_capacity(Some(ResourceType::Energy)) > 0 =>
+            {
+                info!("{}: picking", name);
+                match sight(position.look_for(look::ENERGY), |_| true) {
+                    Sighting::Found(resource) => {
+                        if creep.pos().is_near_to(*position) {
+                            visuals::note_pickup(&name, *position);
+                            intents::act(creep, || creep.pickup(&resource)).unwrap_or_else(|_| {
+                                creep_targets.remove(&name);
+                            });
+                        } else {
+                            let _ = movement::travel_to(creep, *position);
+                        }
+                    }
+                    Sighting::Gone => {
+                        creep_targets.remove(&name);
+                    }
+                    Sighting::OutOfSight => {
+                        out_of_sight(&name, *position);
+                        let _ = movement::travel_to(creep, *position);
+                    }
+                }
+            }
+            CreepTarget::Deposit(position)
+                if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 =>
             {
                 info!("{}: depositing", name);
-                let targets = position.look_for(look::STRUCTURES).unwrap_or_else(|_| {
-                    Vec::new()
-                });
-                let structure = targets.iter().find(|s| {
-                    matches!(s, StructureObject::StructureExtension(_) | StructureObject::StructureSpawn(_) | StructureObject::StructureTower(_))
-                });
+                let position = *position;
+                let structure = match sight(position.look_for(look::STRUCTURES), |s| {
+                    matches!(s, StructureObject::StructureExtension(_) | StructureObject::StructureSpawn(_) | StructureObject::StructureTower(_) | StructureObject::StructureStorage(_))
+                        || (matches!(s, StructureObject::StructureLink(_) | StructureObject::StructureContainer(_)) && upgrading::supply_drop(&creep.room().unwrap()) == Some(position))
+                }) {
+                    Sighting::Found(structure) => Some(structure),
+                    Sighting::Gone => None,
+                    Sighting::OutOfSight => {
+                        out_of_sight(&name, position);
+                        let _ = movement::travel_to(creep, position);
+                        return;
+                    }
+                };
                 if let Some(structure) = structure {
+                    // range 1 takes in the diagonals, and range 0 a carrier standing on the
+                    // controller container, which deposits from where it is without moving
                     if creep.pos().is_near_to(structure.pos()) {
                         if let Some(structure) = structure.as_transferable() {
-                            creep.transfer(structure, ResourceType::Energy, None).unwrap_or_else(|e| {
-                                creep_targets.remove(&name);
+                            visuals::note_delivery(&name, position, creep.store().get_used_capacity(Some(ResourceType::Energy)));
+                            intents::act(creep, || creep.transfer(structure, ResourceType::Energy, None)).unwrap_or_else(|e| match e {
+                                ErrorCode::Full => {
+                                    // the target filled up while we were on our way, go straight to the
+                                    // next one instead of idling a tick waiting for reassignment
+                                    let room = creep.room().expect("couldn't resolve creep room");
+                                    if let Some(next) = next_deposit_target(&room, position) {
+                                        info!("{}: deposit target full, moving on to {:?}", name, next);
+                                        let _ = movement::travel_to(creep, next);
+                                        repair_in_passing(creep);
+                                        creep_targets.insert(name.clone(), CreepTarget::Deposit(next));
+                                    } else {
+                                        creep_targets.remove(&name);
+                                    }
+                                }
+                                _ => {
+                                    creep_targets.remove(&name);
+                                }
                             });
                         }
                     } else {
-                        let _ = creep.move_to(*position);
+                        let _ = movement::travel_to(creep, position);
+                        repair_in_passing(creep);
                     }
                 } else {
                     creep_targets.remove(&name);
@@ -369,19 +1278,304 @@ fn run_creep(creep: &Creep, creep_targets: &mut HashMap<String, CreepTarget>) {
             {
                 info!("{}: repairing", name);
                 if creep.pos().is_near_to(*position) {
-                    if let Ok(structures) = position.look_for(look::STRUCTURES) {
-                        if let Some(structure) = structures.iter().find(|s| s.as_structure().hits() < s.as_structure().hits_max()) {
-                            if let Some(repairable) = structure.as_repairable() {
-                                creep.repair(repairable).unwrap_or_else(|e| {
+                    match sight(position.look_for(look::STRUCTURES), |s| defense::needs_repair(s) || defense::needs_reinforcing(s)) {
+                        Sighting::Found(structure) => match structure.as_repairable() {
+                            Some(repairable) => {
+                                intents::act(creep, || creep.repair(repairable)).unwrap_or_else(|_| {
                                     creep_targets.remove(&name);
                                 });
-                                return;
                             }
+                            None => {
+                                creep_targets.remove(&name);
+                            }
+                        },
+                        Sighting::Gone => {
+                            creep_targets.remove(&name);
+                        }
+                        Sighting::OutOfSight => out_of_sight(&name, *position),
+                    }
+                } else {
+                    let _ = movement::travel_to(creep, *position);
+                    repair_in_passing(creep);
+                }
+            }
+            CreepTarget::Heal(creep_id) => {
+                info!("{}: healing", name);
+                match creep_id.resolve() {
+                    // the patient died, left the room or is already fully healed
+                    Some(patient) if patient.room().map(|r| r.name()) == creep.room().map(|r| r.name()) && patient.hits() < patient.hits_max() => {
+                        if creep.pos().is_near_to(patient.pos()) {
+                            let _ = intents::act(creep, || creep.heal(&patient));
+                        } else {
+                            if creep.pos().in_range_to(patient.pos(), 3) {
+                                let _ = intents::act(creep, || creep.ranged_heal(&patient));
+                            }
+                            let _ = movement::move_to(creep, &patient);
+                        }
+                    }
+                    _ => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::Boost(lab_id) => {
+                info!("{}: boosting", name);
+                match lab_id.resolve() {
+                    Some(lab) if creep.pos().is_near_to(lab.pos()) => {
+                        if let Err(e) = lab.boost_creep(creep, None) {
+                            warn!("couldn't boost: {:?}", e);
+                        }
+                        // boosted or not, get on with fighting
+                        creep_targets.remove(&name);
+                    }
+                    Some(lab) => {
+                        let _ = movement::move_to(creep, &lab);
+                    }
+                    None => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::Attack(creep_id) => {
+                info!("{}: attacking", name);
+                match creep_id.resolve() {
+                    Some(hostile) if hostile.room().map(|r| r.name()) == creep.room().map(|r| r.name()) => {
+                        intents::act(creep, || creep.attack(&hostile)).unwrap_or_else(|e| match e {
+                            ErrorCode::NotInRange => {
+                                let _ = movement::move_to(creep, &hostile);
+                            }
+                            _ => {
+                                warn!("couldn't attack: {:?}", e);
+                                creep_targets.remove(&name);
+                            }
+                        });
+                    }
+                    _ => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::Guard(position) => {
+                info!("{}: guarding", name);
+                let room = creep.room().expect("couldn't resolve creep room");
+                if room.find(find::HOSTILE_CREEPS, None).is_empty() {
+                    creep_targets.remove(&name);
+                } else {
+                    if creep.pos() != *position {
+                        let _ = movement::move_to(creep, *position);
+                    }
+                    // hold the rampart and hit whatever comes up against it
+                    if let Some(hostile) = creep.pos().find_in_range(find::HOSTILE_CREEPS, 1).into_iter().next() {
+                        if let Err(e) = intents::act(creep, || creep.attack(&hostile)) {
+                            warn!("couldn't attack: {:?}", e);
+                        }
+                    }
+                }
+            }
+            CreepTarget::FillLab(lab_id, resource) => {
+                info!("{}: filling lab", name);
+                let resource = *resource;
+                match lab_id.resolve() {
+                    Some(lab) if creep.store().get_used_capacity(Some(resource)) > 0 => {
+                        if creep.pos().is_near_to(lab.pos()) {
+                            if let Err(e) = intents::act(creep, || creep.transfer(&lab, resource, None)) {
+                                warn!("couldn't fill lab: {:?}", e);
+                            }
+                            creep_targets.remove(&name);
+                        } else {
+                            let _ = movement::move_to(creep, &lab);
+                        }
+                    }
+                    Some(lab) => {
+                        // only take as much as the lab has room for, so there's nothing left over
+                        let amount = (lab.store().get_free_capacity(Some(resource)).max(0) as u32)
+                            .min(creep.store().get_free_capacity(Some(resource)).max(0) as u32);
+                        let room = creep.room().expect("couldn't resolve creep room");
+                        let result = match (room.storage(), room.terminal()) {
+                            (Some(storage), _) if storage.store().get_used_capacity(Some(resource)) > 0 => fetch(creep, &storage, resource, amount),
+                            (_, Some(terminal)) if terminal.store().get_used_capacity(Some(resource)) > 0 => fetch(creep, &terminal, resource, amount),
+                            _ => Err(ErrorCode::NotEnough),
+                        };
+                        if let Err(e) = result {
+                            warn!("couldn't fetch {:?} for a lab: {:?}", resource, e);
+                            creep_targets.remove(&name);
+                        }
+                    }
+                    None => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::EmptyLab(lab_id) => {
+                info!("{}: emptying lab", name);
+                let room = creep.room().expect("couldn't resolve creep room");
+                let carried = creep.store().store_types().into_iter().find(|resource| *resource != ResourceType::Energy);
+                match (lab_id.resolve(), carried) {
+                    // into the storage, or the terminal once that's full
+                    (_, Some(resource)) => {
+                        let result = match (room.storage(), room.terminal()) {
+                            (Some(storage), _) if storage.store().get_free_capacity(Some(resource)) > 0 => stash(creep, &storage, resource),
+                            (_, Some(terminal)) if terminal.store().get_free_capacity(Some(resource)) > 0 => stash(creep, &terminal, resource),
+                            _ => Err(ErrorCode::Full),
+                        };
+                        // one load per trip, the lab comes up again if there's still a batch in it
+                        match result {
+                            Ok(true) => {
+                                creep_targets.remove(&name);
+                            }
+                            Ok(false) => {}
+                            Err(e) => {
+                                warn!("couldn't put away {:?} from a lab: {:?}", resource, e);
+                                creep_targets.remove(&name);
+                            }
+                        }
+                    }
+                    (Some(lab), None) => match lab.mineral_type() {
+                        Some(mineral) => {
+                            let amount = lab.store().get_used_capacity(Some(mineral)).min(creep.store().get_free_capacity(Some(mineral)).max(0) as u32);
+                            if let Err(e) = fetch(creep, &lab, mineral, amount) {
+                                warn!("couldn't empty lab: {:?}", e);
+                                creep_targets.remove(&name);
+                            }
+                        }
+                        None => {
+                            creep_targets.remove(&name);
+                        }
+                    },
+                    (None, None) => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::StockTerminal => {
+                info!("{}: stocking the terminal", name);
+                let room = creep.room().expect("couldn't resolve creep room");
+                match (room.storage(), room.terminal()) {
+                    (Some(_), Some(terminal)) if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 => {
+                        match stash(creep, &terminal, ResourceType::Energy) {
+                            Ok(true) => {
+                                creep_targets.remove(&name);
+                            }
+                            Ok(false) => {}
+                            Err(e) => {
+                                warn!("couldn't stock the terminal: {:?}", e);
+                                creep_targets.remove(&name);
+                            }
+                        }
+                    }
+                    (Some(storage), Some(_)) => {
+                        let amount = terminal::shortfall(&room).min(creep.store().get_free_capacity(Some(ResourceType::Energy)).max(0) as u32);
+                        if amount == 0 {
+                            creep_targets.remove(&name);
+                        } else if let Err(e) = fetch(creep, &storage, ResourceType::Energy, amount) {
+                            warn!("couldn't fetch energy for the terminal: {:?}", e);
+                            creep_targets.remove(&name);
+                        }
+                    }
+                    _ => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::UnloadTerminal => {
+                info!("{}: unloading the terminal", name);
+                let room = creep.room().expect("couldn't resolve creep room");
+                match (room.storage(), room.terminal()) {
+                    (Some(storage), Some(_)) if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 => {
+                        match stash(creep, &storage, ResourceType::Energy) {
+                            Ok(true) => {
+                                creep_targets.remove(&name);
+                            }
+                            Ok(false) => {}
+                            Err(e) => {
+                                warn!("couldn't put the terminal's energy away: {:?}", e);
+                                creep_targets.remove(&name);
+                            }
+                        }
+                    }
+                    (Some(_), Some(terminal)) => {
+                        let amount = terminal::surplus(&room).min(creep.store().get_free_capacity(Some(ResourceType::Energy)).max(0) as u32);
+                        if amount == 0 {
+                            creep_targets.remove(&name);
+                        } else if let Err(e) = fetch(creep, &terminal, ResourceType::Energy, amount) {
+                            warn!("couldn't fetch energy from the terminal: {:?}", e);
+                            creep_targets.remove(&name);
+                        }
+                    }
+                    _ => {
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::Reserve(controller_id) => {
+                info!("{}: reserving", name);
+                if let Some(controller) = controller_id.resolve() {
+                    if creep.pos().is_near_to(controller.pos()) {
+                        if let Err(e) = intents::act(creep, || creep.reserve_controller(&controller)) {
+                            warn!("couldn't reserve {}: {:?}", controller.pos().room_name(), e);
+                            creep_targets.remove(&name);
+                        }
+                    } else {
+                        let _ = movement::travel_to(creep, controller.pos());
+                    }
+                }
+            }
+            CreepTarget::FeedTowers => {
+                info!("{}: feeding towers", name);
+                let room = creep.room().expect("couldn't resolve creep room");
+                if room.find(find::HOSTILE_CREEPS, None).is_empty() {
+                    // back to the general pool once the room's clear
+                    creep_targets.remove(&name);
+                } else if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 {
+                    if let Some(tower) = tower::hungriest_tower(&room) {
+                        if creep.pos().is_near_to(tower.pos()) {
+                            if let Err(e) = intents::act(creep, || creep.transfer(&tower, ResourceType::Energy, None)) {
+                                warn!("couldn't feed tower: {:?}", e);
+                            }
+                        } else {
+                            let _ = movement::move_to(creep, &tower);
                         }
                     }
+                } else {
+                    let amount = creep.store().get_free_capacity(Some(ResourceType::Energy)).max(0) as u32;
+                    let result = match tower::energy_buffer(&room, creep.pos()) {
+                        Some(StructureObject::StructureStorage(storage)) => fetch(creep, &storage, ResourceType::Energy, amount),
+                        Some(StructureObject::StructureLink(link)) => fetch(creep, &link, ResourceType::Energy, amount),
+                        Some(StructureObject::StructureContainer(container)) => fetch(creep, &container, ResourceType::Energy, amount),
+                        _ => Err(ErrorCode::NotEnough),
+                    };
+                    if let Err(e) = result {
+                        warn!("couldn't fetch energy for the towers: {:?}", e);
+                        creep_targets.remove(&name);
+                    }
+                }
+            }
+            CreepTarget::Dump(position) => {
+                info!("{}: dumping energy for the upgraders", name);
+                if creep.pos().in_range_to(*position, DUMP_RANGE) {
+                    if let Err(e) = intents::act(creep, || creep.drop(ResourceType::Energy, None)) {
+                        warn!("couldn't dump energy: {:?}", e);
+                    }
                     creep_targets.remove(&name);
                 } else {
-                    let _ = creep.move_to(*position);
+                    let _ = movement::travel_to(creep, *position);
+                }
+            }
+            CreepTarget::Recycle(spawn_id) => {
+                info!("{}: going to be recycled", name);
+                match spawn_id.resolve() {
+                    Some(spawn) if creep.pos().is_near_to(spawn.pos()) => {
+                        if let Err(e) = spawn.recycle_creep(creep) {
+                            warn!("couldn't recycle {}: {:?}", name, e);
+                            creep_targets.remove(&name);
+                        }
+                    }
+                    Some(spawn) => {
+                        let _ = movement::move_to(creep, &spawn);
+                    }
+                    None => {
+                        creep_targets.remove(&name);
+                    }
                 }
             }
             _ => {
@@ -392,174 +1586,739 @@ fn run_creep(creep: &Creep, creep_targets: &mut HashMap<String, CreepTarget>) {
     }
 }
 
-fn assign_new_targets(creep_targets: &mut HashMap<String, CreepTarget>) {
+fn defender_body_for(spawn: &StructureSpawn, energy: u32) -> Vec<Part> {
+    let sieged = defense::under_siege(&spawn.room().unwrap());
+    if sieged {
+        debug!("{} is under siege, spawning a tanky defender", spawn.room().unwrap().name());
+    }
+    defense::defender_body(energy, sieged)
+}
+
+// don't let upgraders touch storage energy below this, it's kept for reconstruction and defense
+const STORAGE_UPGRADE_FLOOR: u32 = 20000;
+
+// below this many ticks to downgrade we upgrade regardless of the storage reserve
+const DOWNGRADE_PROTECTION_TICKS: u32 = 5000;
+
+// workers kept upgrading a controller someone's attacking, ahead of building anything
+const CONTESTED_UPGRADERS: usize = 2;
+
+// whether creeps with nothing left to build, repair or fill go and upgrade the controller;
+// without it their energy goes into storage, or gets left by the controller with no storage
+const IDLE_UPGRADE: bool = true;
+
+// creeps looking for energy go for containers and dropped piles holding at least this
+// percentage of what they can carry, rather than waiting around for a full load to build up
+const PICKUP_MIN_PERCENT: u32 = 25;
+
+// a creep heading off to upgrade with less than this much of a load tops up first, rather
+// than walking to the controller to spend a dribble of energy and straight back out again
+const IDLE_REFILL_BELOW_PERCENT: u32 = 50;
+
+// whether upgrading should wait because the room's storage is below its reserve; rooms without
+// a storage have no reserve to protect
+fn upgrades_paused(room: &Room, controller: &StructureController) -> bool {
+    let storage_energy = match room.storage() {
+        Some(storage) => storage.store().get_used_capacity(Some(ResourceType::Energy)),
+        None => return false,
+    };
+    storage_energy < STORAGE_UPGRADE_FLOOR && controller.ticks_to_downgrade().is_some_and(|ticks| ticks > DOWNGRADE_PROTECTION_TICKS)
+}
+
+// while a creep with energy and WORK parts is walking somewhere anyway, let it top off a
+// damaged road or container right next to it; it's only done on ticks the creep is moving
+// rather than doing its actual job, so it never gets in the way of its target, and walls and
+// ramparts are left alone since they'd soak up everything it carries
+fn repair_in_passing(creep: &Creep) {
+    if creep.store().get_used_capacity(Some(ResourceType::Energy)) == 0 || creep.get_active_bodyparts(Part::Work) == 0 {
+        return;
+    }
+    let damaged = creep.pos().find_in_range(find::STRUCTURES, 1)
+        .into_iter()
+        .filter(|s| matches!(s, StructureObject::StructureRoad(_) | StructureObject::StructureContainer(_)))
+        .find(|s| s.as_structure().hits() < s.as_structure().hits_max());
+    if let Some(repairable) = damaged.as_ref().and_then(|s| s.as_repairable()) {
+        debug!("{}: repairing in passing", creep.name());
+        let _ = intents::act(creep, || creep.repair(repairable));
+    }
+}
+
+// builders keep reinforcing a rampart they just finished until it has this many hits
+const FRESH_RAMPART_HITS: u32 = 10000;
+
+// how much a carrier takes out of a spawn when there's no other energy to keep the
+// controller from downgrading
+const EMERGENCY_WITHDRAW_AMOUNT: u32 = 50;
+
+// a spawn we can take a little energy out of as a last resort to feed the controller; this
+// only happens when it's about to downgrade, the room is at its creep cap (so nothing is
+// waiting to be spawned) and the spawn isn't busy
+fn emergency_withdraw_spawn(room: &Room) -> Option<StructureSpawn> {
+    let controller = room.controller()?;
+    if controller.ticks_to_downgrade().is_none_or(|ticks| ticks > DOWNGRADE_PROTECTION_TICKS) {
+        return None;
+    }
+    if population::creep_count(room) < population::max_creeps(room) {
+        return None;
+    }
+    room.find(find::MY_SPAWNS, None)
+        .into_iter()
+        .find(|spawn| spawn.spawning().is_none() && spawn.store().get_used_capacity(Some(ResourceType::Energy)) >= EMERGENCY_WITHDRAW_AMOUNT)
+}
+
+// the harvesters mining a source, and how many WORK parts they have between them
+fn source_miners(source: &Source, creep_targets: &HashMap<String, CreepTarget>) -> (u32, u32) {
+    let mut harvesters = 0;
+    let mut work_parts = 0;
+    for (name, target) in creep_targets.iter() {
+        if matches!(target, CreepTarget::Harvest(id) if *id == source.id()) {
+            // a harvester that's due to be replaced leaves room for its replacement
+            if let Some(creep) = game::creeps().get(name.clone()).filter(|creep| !population::replacement_due(creep)) {
+                harvesters += 1;
+                work_parts += creep.get_active_bodyparts(Part::Work) as u32;
+            }
+        }
+    }
+    (harvesters, work_parts)
+}
+
+// whether a source can take another harvester: there has to be a free tile next to it, and
+// the harvesters already assigned mustn't be saturating it
+fn source_has_room(source: &Source, creep_targets: &HashMap<String, CreepTarget>) -> bool {
+    let (harvesters, work_parts) = source_miners(source, creep_targets);
+    harvesters < mining::open_tiles(source) && work_parts < mining::work_parts_needed(source)
+}
+
+// remote miners take a source in their remote room nobody else has; remote carriers pick up
+// what the miners drop (or withdraw from a container there) until they're full, then bring
+// it all the way home
+fn assign_remote_target(creep: &Creep, role: Role, remote: RoomName, creep_targets: &mut HashMap<String, CreepTarget>) {
+    let name = creep.name();
+    if role == Role::Reserver {
+        match game::rooms().get(remote).and_then(|room| room.controller()) {
+            Some(controller) => {
+                creep_targets.insert(name, CreepTarget::Reserve(controller.id()));
+            }
+            None => remote::head_to(creep, remote),
+        }
+        return;
+    }
+    if role == Role::Harvester {
+        let taken = creep_targets.values().filter_map(|target| match target {
+            CreepTarget::Harvest(id) => Some(*id),
+            _ => None,
+        }).collect::<Vec<_>>();
+        match remote::free_source(remote, &taken) {
+            Some(source) => {
+                creep_targets.insert(name, CreepTarget::Harvest(source));
+            }
+            None => remote::head_to(creep, remote),
+        }
+        return;
+    }
+
+    let energy = creep.store().get_used_capacity(Some(ResourceType::Energy));
+    if creep.store().get_free_capacity(Some(ResourceType::Energy)) > 0 {
+        if let Some(room) = game::rooms().get(remote) {
+            let dropped = room.find(find::DROPPED_RESOURCES, None)
+                .into_iter()
+                .filter(|resource| resource.resource_type() == ResourceType::Energy)
+                .max_by_key(|resource| resource.amount());
+            let worth_withdrawing = if energy > 0 { remote::ROUTE_MIN_ENERGY } else { 1 };
+            let container = room.find(find::STRUCTURES, None)
+                .into_iter()
+                .filter_map(|s| match s {
+                    StructureObject::StructureContainer(container) if container.store().get_used_capacity(Some(ResourceType::Energy)) >= worth_withdrawing => Some(container),
+                    _ => None,
+                })
+                .max_by_key(|container| container.store().get_used_capacity(Some(ResourceType::Energy)));
+            if let Some(container) = container {
+                creep_targets.insert(name, CreepTarget::Withdraw(container.id(), None));
+                return;
+            } else if let Some(resource) = dropped {
+                creep_targets.insert(name, CreepTarget::Pickup(resource.pos()));
+                return;
+            }
+        }
+        // with its own remote emptied, a carrier that's already picked something up fills
+        // the rest of the way from the other remotes nearby before heading home, a container
+        // at a time until it's full or there's nothing left on the way
+        if energy > 0 {
+            let taken = creep_targets.iter().filter_map(|(other, target)| match target {
+                CreepTarget::Withdraw(id, _) if *other != name => Some(*id),
+                _ => None,
+            }).collect::<Vec<_>>();
+            if let Some(container) = remote::next_on_route(creep, &taken) {
+                info!("{}: topping up from {} on the way home", name, container.pos().room_name());
+                creep_targets.insert(name, CreepTarget::Withdraw(container.id(), None));
+                return;
+            }
+        }
+        if energy == 0 {
+            remote::head_to(creep, remote);
+            return;
+        }
+    }
+
+    let home = remote::home_room(creep).and_then(|home| game::rooms().get(home));
+    if let Some(home) = home {
+        let storage = home.storage().map(|storage| storage.pos());
+        let exclude = storage.unwrap_or_else(|| creep.pos());
+        if let Some(target) = next_deposit_target(&home, exclude).or(storage) {
+            creep_targets.insert(name, CreepTarget::Deposit(target));
+        }
+    }
+}
+
+// who gets the energy first when there isn't enough to go around, lowest first. spawns and
+// extensions are one and the same as far as spawning goes so they share a priority, towers
+// come after them - unless there are hostiles in the room, then keeping the towers shooting
+// comes before anything else. then carriers keep the upgraders' supply (see `upgrading`)
+// going, storage takes whatever's left, and building and upgrading only
+// happen with energy once everything up to (but not including) storage is full
+const FILL_PRIORITY_TOWER_IN_COMBAT: u8 = 0;
+const FILL_PRIORITY_SPAWNING: u8 = 1;
+const FILL_PRIORITY_TOWER: u8 = 2;
+const FILL_PRIORITY_UPGRADE_SUPPLY: u8 = 3;
+const FILL_PRIORITY_STORAGE: u8 = 4;
+
+// the kinds of construction site, in the order they get built by default: defenses (ramparts,
+// walls and towers) first, then containers, extensions and everything else.
+// `Memory.buildPriority` can reorder them, e.g. `["extension", "defense", "container", "other"]`
+// during a push for energy capacity; anything it leaves out keeps its default place after the
+// ones it lists
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BuildCategory {
+    Defense,
+    Container,
+    Extension,
+    Other,
+}
+
+const DEFAULT_BUILD_ORDER: [BuildCategory; 4] = [BuildCategory::Defense, BuildCategory::Container, BuildCategory::Extension, BuildCategory::Other];
+
+impl BuildCategory {
+    fn of(structure_type: StructureType) -> BuildCategory {
+        match structure_type {
+            StructureType::Rampart | StructureType::Wall | StructureType::Tower => BuildCategory::Defense,
+            StructureType::Container => BuildCategory::Container,
+            StructureType::Extension => BuildCategory::Extension,
+            _ => BuildCategory::Other,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<BuildCategory> {
+        match name {
+            "defense" => Some(BuildCategory::Defense),
+            "container" => Some(BuildCategory::Container),
+            "extension" => Some(BuildCategory::Extension),
+            "other" => Some(BuildCategory::Other),
+            _ => None,
+        }
+    }
+}
+
+// the order to build in this tick, from `Memory.buildPriority` if it's set and makes sense
+fn build_order() -> Vec<BuildCategory> {
+    let Ok(value) = Reflect::get(&screeps::memory::ROOT, &JsString::from("buildPriority")) else {
+        return DEFAULT_BUILD_ORDER.to_vec();
+    };
+    if value.is_undefined() {
+        return DEFAULT_BUILD_ORDER.to_vec();
+    }
+    let names = js_sys::Array::is_array(&value).then(|| js_sys::Array::from(&value).iter().map(|name| name.as_string()).collect::<Option<Vec<_>>>()).flatten();
+    let listed = names.as_ref().and_then(|names| names.iter().map(|name| BuildCategory::from_name(name)).collect::<Option<Vec<_>>>());
+    let Some(mut order) = listed else {
+        let config = format!("{:?}", value);
+        BAD_BUILD_PRIORITY.with(|bad| {
+            if bad.borrow().as_deref() != Some(config.as_str()) {
+                warn!("ignoring Memory.buildPriority {}, it should be a list of defense, container, extension and other", config);
+                *bad.borrow_mut() = Some(config);
+            }
+        });
+        return DEFAULT_BUILD_ORDER.to_vec();
+    };
+    for category in DEFAULT_BUILD_ORDER {
+        if !order.contains(&category) {
+            order.push(category);
+        }
+    }
+    order
+}
+
+// the energy already being carried to each deposit target by the creeps that have been sent there
+fn deposits_on_the_way(creep_targets: &HashMap<String, CreepTarget>) -> HashMap<Position, u32> {
+    let mut on_the_way = HashMap::new();
+    for (name, target) in creep_targets {
+        if let CreepTarget::Deposit(pos) = target {
+            if let Some(creep) = game::creeps().get(name.clone()) {
+                *on_the_way.entry(*pos).or_insert(0) += creep.store().get_used_capacity(Some(ResourceType::Energy));
+            }
+        }
+    }
+    on_the_way
+}
+
+// what looking at a target's tile says to do with the target
+#[derive(Debug, PartialEq)]
+enum Sighting<T> {
+    // the first thing there the target's after, to act on
+    Found(T),
+    // nothing there for it, so the target's done with
+    Gone,
+    // the tile couldn't be looked at, so the target's kept to look at again next tick
+    OutOfSight,
+}
+
+// `look_for` only fails when the room isn't visible, which is usually just for a tick or so
+// (like when the creep's stepping between rooms), so a target that can't be looked at stays
+// put and gets checked again next tick; it's finding nothing there that clears it
+fn sight<T, E>(looked: Result<Vec<T>, E>, wanted: impl Fn(&T) -> bool) -> Sighting<T> {
+    match looked {
+        Ok(found) => found.into_iter().find(|item| wanted(item)).map_or(Sighting::Gone, Sighting::Found),
+        Err(_) => Sighting::OutOfSight,
+    }
+}
+
+fn out_of_sight(name: &str, position: Position) {
+    debug!("{}: can't see {} to check on its target, trying again next tick", name, position);
+}
+
+// how much more energy the structure at `pos` has room for
+fn free_energy_capacity_at(pos: Position) -> u32 {
+    pos.look_for(look::STRUCTURES)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|s| s.as_has_store().map(|s| s.store().get_free_capacity(Some(ResourceType::Energy)).max(0) as u32))
+        .max()
+        .unwrap_or(0)
+}
+
+// where a structure comes in the order of things that get filled with energy, None for ones
+// that don't get filled at all
+fn fill_priority(structure: &StructureObject, in_combat: bool) -> Option<u8> {
+    match structure {
+        StructureObject::StructureTower(_) if in_combat => Some(FILL_PRIORITY_TOWER_IN_COMBAT),
+        StructureObject::StructureSpawn(_) | StructureObject::StructureExtension(_) => Some(FILL_PRIORITY_SPAWNING),
+        StructureObject::StructureTower(_) => Some(FILL_PRIORITY_TOWER),
+        StructureObject::StructureStorage(storage) if !defense::storage_nearly_full(storage) => Some(FILL_PRIORITY_STORAGE),
+        _ => None,
+    }
+}
+
+// extra on top of what the spawns, extensions and towers are short by when withdrawing just
+// enough to top them off, so a bit of drain on the way doesn't leave one of them unfilled
+const TOP_OFF_MARGIN: u32 = 50;
+
+// how much a hauler should take from a container when the room only needs a little to be
+// topped off; None means take a full load, either because the shortfall is more than we can
+// carry anyway, or there's nothing to top off and it's all going into construction or storage
+fn top_off_amount(creep: &Creep, room: &Room) -> Option<u32> {
+    if !Role::of(creep).carries() {
+        return None;
+    }
+    let shortfall: u32 = room.find(find::MY_STRUCTURES, None)
+        .iter()
+        .filter(|s| matches!(s, StructureObject::StructureSpawn(_) | StructureObject::StructureExtension(_) | StructureObject::StructureTower(_)))
+        .filter_map(|s| s.as_has_store())
+        .map(|s| s.store().get_free_capacity(Some(ResourceType::Energy)).max(0) as u32)
+        .sum();
+    let amount = shortfall + TOP_OFF_MARGIN;
+    (shortfall > 0 && amount < creep.store().get_free_capacity(Some(ResourceType::Energy)) as u32).then_some(amount)
+}
+
+// carriers dumping energy for the upgraders drop it within this range of the controller, the
+// range upgraders work from
+const DUMP_RANGE: u32 = 3;
+
+// the storage, unless it's nearly full; the sink for anything nothing else needs
+fn storage_drop(room: &Room) -> Option<Position> {
+    room.storage()
+        .filter(|storage| !defense::storage_nearly_full(storage))
+        .map(|storage| storage.pos())
+}
+
+// the end of the line for energy nothing else wants: the storage, or failing that the ground
+// by the controller for the upgraders, so a hauler never sits on a full load
+fn energy_sink(room: &Room) -> Option<CreepTarget> {
+    storage_drop(room)
+        .map(CreepTarget::Deposit)
+        .or_else(|| room.controller().map(|controller| CreepTarget::Dump(controller.pos())))
+}
+
+// whether a hauler would put energy dumped by the controller to use rather than only dumping
+// it again, which it does if it can't upgrade
+fn upgrades_with_dumped(creep: &Creep) -> bool {
+    IDLE_UPGRADE && creep.get_active_bodyparts(Part::Work) > 0
+}
+
+// find the next-best place to drop off energy, skipping `exclude` (usually a target that just
+// turned out to be full); extensions and spawns come first, then towers, with storage as the
+// sink of last resort
+fn next_deposit_target(room: &Room, exclude: Position) -> Option<Position> {
+    let in_combat = !room.find(find::HOSTILE_CREEPS, None).is_empty();
+    room.find(find::MY_STRUCTURES, None)
+        .iter()
+        .filter(|s| s.pos() != exclude && s.as_has_store().is_some_and(|s| s.store().get_free_capacity(Some(ResourceType::Energy)) > 0))
+        .filter_map(|s| fill_priority(s, in_combat).map(|priority| (priority, s.pos())))
+        .min_by_key(|(priority, pos)| (*priority, pos.get_range_to(exclude)))
+        .map(|(_, pos)| pos)
+}
+
+// tasks are handed out by role first - medics heal, defenders fight, harvesters only ever
+// harvest (even the ones with a CARRY for their link), and only carriers and workers move
+// energy around
+fn assign_new_targets(creep_targets: &mut HashMap<String, CreepTarget>, live_creeps: &HashSet<String>) {
+    let build_order = build_order();
     'creeps: for creep in game::creeps().values() {
         let name = creep.name();
         if !creep_targets.contains_key(&name) {
             info!("{}: assigning", name);
             let room = creep.room().expect("couldn't resolve creep room");
-            if creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 {
-                // Assign the creep to fill energy
-                let spawns = room.find(find::MY_STRUCTURES, None)
+            let role = Role::of(&creep);
+            if squad::squad_of(&creep).is_some() || harass::raid_of(&creep).is_some() || shuttle::is_shuttle(&creep) {
+                continue;
+            } else if let Some(remote) = remote::remote_room(&creep) {
+                assign_remote_target(&creep, role, remote, creep_targets);
+            } else if let Some(spawn) = population::worth_recycling(&creep, &room).then(|| creep.pos().find_closest_by_range(find::MY_SPAWNS)).flatten() {
+                // a replacement gets spawned once it's gone like for any other creep
+                warn!("{}: too damaged to be worth keeping, recycling it", name);
+                creep_targets.insert(name, CreepTarget::Recycle(spawn.id()));
+            } else if role == Role::Medic {
+                // medics patch up whoever is the most hurt
+                let patient = room.find(find::MY_CREEPS, None)
                     .into_iter()
-                    .filter_map(|s| match s {
-                        StructureObject::StructureSpawn(spawn) if spawn.store().get_free_capacity(Some(ResourceType::Energy)) > 0 => Some(spawn),
-                        _ => None
-                    })
-                    .collect::<Vec<_>>();
-                let extensions = room.find(find::MY_STRUCTURES, None)
+                    .filter(|c| c.hits() < c.hits_max())
+                    .min_by_key(|c| c.hits() * 100 / c.hits_max());
+                if let Some(patient_id) = patient.and_then(|patient| patient.try_id()) {
+                    creep_targets.insert(name, CreepTarget::Heal(patient_id));
+                }
+            } else if role == Role::Defender {
+                // under siege, pick up a TOUGH boost on the way if a lab has one ready
+                if defense::under_siege(&room) {
+                    if let Some(lab) = defense::tough_boost_lab(&creep, &room) {
+                        creep_targets.insert(name, CreepTarget::Boost(lab.id()));
+                        continue;
+                    }
+                }
+                // hold an entrance rampart facing the hostiles if there's one free, otherwise go
+                // after the closest one in the open
+                let taken = creep_targets.values().filter_map(|target| match target {
+                    CreepTarget::Guard(position) => Some(*position),
+                    _ => None,
+                }).collect::<Vec<_>>();
+                if let Some(rampart) = defense::entrance_rampart(&room, &taken) {
+                    creep_targets.insert(name, CreepTarget::Guard(rampart));
+                } else if let Some(hostile_id) = room.find(find::HOSTILE_CREEPS, None)
                     .into_iter()
-                    .filter_map(|s| match s {
-                        StructureObject::StructureExtension(ext) if ext.store().get_free_capacity(Some(ResourceType::Energy)) > 0 => Some(ext),
-                        _ => None
-                    })
-                    .collect::<Vec<_>>();
-                let towers = room.find(find::MY_STRUCTURES, None)
+                    .min_by_key(|hostile| (!defense::is_siphoner(hostile), creep.pos().get_range_to(hostile.pos())))
+                    .and_then(|hostile| hostile.try_id())
+                {
+                    // siphoners first, they're the ones actually costing us something
+                    creep_targets.insert(name, CreepTarget::Attack(hostile_id));
+                }
+            } else if role.carries() && tower::needs_feeding(&room) && !creep_targets.iter().any(|(other, target)| {
+                matches!(target, CreepTarget::FeedTowers) && game::creeps().get(other.clone()).is_some_and(|other| other.pos().room_name() == room.name())
+            }) {
+                info!("{}: keeping the towers fed while the room's under attack", name);
+                creep_targets.insert(name, CreepTarget::FeedTowers);
+            } else if role.carries() && creep.store().get_used_capacity(Some(ResourceType::Energy)) > 0 {
+                // Assign the creep to fill energy, whatever needs it most first
+                let in_combat = !room.find(find::HOSTILE_CREEPS, None).is_empty();
+                let mut needs_energy = room.find(find::MY_STRUCTURES, None)
                     .into_iter()
-                    .filter_map(|s| match s {
-                        StructureObject::StructureTower(tower) if tower.store().get_free_capacity(Some(ResourceType::Energy)) > 0 => Some(tower),
-                        _ => None
-                    })
+                    .filter(|s| s.as_has_store().is_some_and(|s| s.store().get_free_capacity(Some(ResourceType::Energy)) > 0))
+                    .filter_map(|s| fill_priority(&s, in_combat).filter(|&priority| priority < FILL_PRIORITY_STORAGE).map(|priority| (priority, s.pos())))
                     .collect::<Vec<_>>();
-
-                if let Some(target) = extensions.iter().min_by_key(|ext| ext.store().get_free_capacity(Some(ResourceType::Energy))) {
-                    if !creep_targets.values().any(|target| matches!(target, CreepTarget::Deposit(_))) {
-                        creep_targets.insert(name, CreepTarget::Deposit(target.pos()));
-                        continue;
+                // one hauler at a time keeps the upgraders supplied too, the rest are more use
+                // building and upgrading themselves
+                if let Some(drop) = upgrading::supply_drop(&room) {
+                    if !creep_targets.values().any(|target| *target == CreepTarget::Deposit(drop)) {
+                        needs_energy.push((FILL_PRIORITY_UPGRADE_SUPPLY, drop));
                     }
                 }
+                // any number of creeps can be depositing at once, but each structure only gets
+                // as many of them as it takes to fill it, so they spread out over everything that
+                // needs energy rather than all piling onto the same extension
+                let on_the_way = deposits_on_the_way(creep_targets);
+                needs_energy.retain(|(_, pos)| on_the_way.get(pos).copied().unwrap_or(0) < free_energy_capacity_at(*pos));
+                let top_priority = needs_energy.iter().map(|(priority, _)| *priority).min();
 
-                if let Some(target) = spawns.iter().min_by_key(|spawn| spawn.store().get_free_capacity(Some(ResourceType::Energy))) {
-                    if !creep_targets.values().any(|target| matches!(target, CreepTarget::Deposit(_))) {
-                        creep_targets.insert(name, CreepTarget::Deposit(target.pos()));
+                // a room that's lost its spawn has nothing more important to do than build a new
+                // one, so anything that can build goes and helps
+                if creep.get_active_bodyparts(Part::Work) > 0 {
+                    let spawn_site = room.find(find::MY_CONSTRUCTION_SITES, None)
+                        .into_iter()
+                        .find(|site| site.structure_type() == StructureType::Spawn);
+                    if let Some(site) = spawn_site {
+                        creep_targets.insert(name, CreepTarget::Construct(site.pos(), site.structure_type()));
                         continue;
                     }
+                    // and during an invasion, the same goes for a room's first tower
+                    let towerless = !room.find(find::MY_STRUCTURES, None).iter().any(|s| matches!(s, StructureObject::StructureTower(_)));
+                    if in_combat && towerless {
+                        let tower_site = room.find(find::MY_CONSTRUCTION_SITES, None)
+                            .into_iter()
+                            .find(|site| site.structure_type() == StructureType::Tower);
+                        if let Some(site) = tower_site {
+                            creep_targets.insert(name, CreepTarget::Construct(site.pos(), site.structure_type()));
+                            continue;
+                        }
+                    }
                 }
 
-                if let Some(target) = towers.iter().min_by_key(|tower| tower.store().get_free_capacity(Some(ResourceType::Energy))) {
-                    if !creep_targets.values().any(|target| matches!(target, CreepTarget::Deposit(_))) {
-                        creep_targets.insert(name, CreepTarget::Deposit(target.pos()));
-                        continue;
-                    }
+                // if we're already standing next to something that needs energy just fill that,
+                // it costs nothing to get to - as long as it's as important as anything else
+                let adjacent = needs_energy.iter()
+                    .find(|(priority, pos)| Some(*priority) == top_priority && creep.pos().is_near_to(*pos));
+                if let Some((_, target)) = adjacent {
+                    creep_targets.insert(name, CreepTarget::Deposit(*target));
+                    continue;
                 }
 
-                // constructors
-                let construction_sites = room.find(find::MY_CONSTRUCTION_SITES, None);
-                let defensive_sites = construction_sites.iter().filter(|site| site.structure_type() == StructureType::Rampart || site.structure_type() == StructureType::Wall || site.structure_type() == StructureType::Tower);
-                let extension_sites = construction_sites.iter().filter(|site| site.structure_type() == StructureType::Extension);
-                let container_sites = construction_sites.iter().filter(|site| site.structure_type() == StructureType::Container);
-                let other_sites = construction_sites.iter().filter(|site| ![StructureType::Extension, StructureType::Container, StructureType::Rampart, StructureType::Wall, StructureType::Tower].contains(&site.structure_type()));
-
-                if let Some(site) = defensive_sites.min_by_key(|site| site.progress_total() - site.progress()) {
-                    if !creep_targets.iter().any(|(name, target)| matches!(target, CreepTarget::Construct(_))) && game::creeps().values().any(|c| c.name() == name.as_str()) {
-                        creep_targets.insert(name, CreepTarget::Construct(site.pos()));
-                        continue;
+                // otherwise the nearest of the most important, rather than walking across the
+                // room to whichever happens to be emptier; only between ones just as close does
+                // the emptier win
+                let nearest = needs_energy.iter()
+                    .min_by_key(|(priority, pos)| (*priority, creep.pos().get_range_to(*pos), std::cmp::Reverse(free_energy_capacity_at(*pos))));
+                if let Some((_, target)) = nearest {
+                    creep_targets.insert(name, CreepTarget::Deposit(*target));
+                    continue;
+                }
+
+                // a hauler that can't build, repair or upgrade (a worker whose WORK parts have
+                // all been shot off) is done once nothing needs filling
+                if creep.get_active_bodyparts(Part::Work) == 0 {
+                    if let Some(sink) = energy_sink(&room) {
+                        creep_targets.insert(name, sink);
                     }
+                    continue;
                 }
 
-                if let Some(site) = container_sites.min_by_key(|site| site.progress_total() - site.progress()) {
-                    if !creep_targets.values().any(|target| matches!(target, CreepTarget::Construct(_))) {
-                        creep_targets.insert(name, CreepTarget::Construct(site.pos()));
-                        continue;
+                // with someone going for the controller, keep a few workers upgrading it to
+                // undo the damage, for as long as it isn't blocked from being upgraded at all
+                if defense::controller_contested(&room) && creep.get_active_bodyparts(Part::Work) > 0 {
+                    if let Some(controller) = room.controller().filter(|controller| controller.upgrade_blocked().is_none()) {
+                        let upgraders = creep_targets.values().filter(|target| **target == CreepTarget::Upgrade(controller.id())).count();
+                        if upgraders < CONTESTED_UPGRADERS {
+                            info!("{}: controller's contested, upgrading it", name);
+                            creep_targets.insert(name, CreepTarget::Upgrade(controller.id()));
+                            continue 'creeps;
+                        }
                     }
                 }
 
-                if let Some(site) = extension_sites.min_by_key(|site| site.progress_total() - site.progress()) {
-                    if !creep_targets.values().any(|target| matches!(target, CreepTarget::Construct(_))) {
-                        creep_targets.insert(name, CreepTarget::Construct(site.pos()));
-                        continue;
+                // constructors, taking the kinds of site in `build_order` and the closest to
+                // finished of each
+                let construction_sites = room.find(find::MY_CONSTRUCTION_SITES, None);
+                for category in build_order.iter() {
+                    let site = construction_sites.iter()
+                        .filter(|site| BuildCategory::of(site.structure_type()) == *category)
+                        .min_by_key(|site| site.progress_total() - site.progress());
+                    if let Some(site) = site {
+                        if !creep_targets.iter().any(|(name, target)| matches!(target, CreepTarget::Construct(..)) && live_creeps.contains(name)) {
+                            creep_targets.insert(name, CreepTarget::Construct(site.pos(), site.structure_type()));
+                            continue 'creeps;
+                        }
                     }
                 }
 
-                if let Some(site) = other_sites.min_by_key(|site| site.progress_total() - site.progress()) {
-                    if !creep_targets.values().any(|target| matches!(target, CreepTarget::Construct(_))) {
-                        creep_targets.insert(name, CreepTarget::Construct(site.pos()));
-                        continue;
+                // nothing to build at home, so help with whatever's going up in the remotes
+                if creep.get_active_bodyparts(Part::Work) > 0 {
+                    if let Some((position, structure_type)) = remote::construction_site(room.name()) {
+                        if !creep_targets.values().any(|target| matches!(target, CreepTarget::Construct(..))) {
+                            info!("{}: building {:?} over in {}", name, structure_type, position.room_name());
+                            creep_targets.insert(name, CreepTarget::Construct(position, structure_type));
+                            continue;
+                        }
                     }
                 }
 
                 // repairers
                 let mut repairable = room.find(find::STRUCTURES, None)
                     .into_iter()
-                    .filter(|s| s.as_repairable().map_or(false, |r| r.hits() < r.hits_max() / 2))
+                    .filter(|s| defense::repair_ceiling(s).is_some_and(|ceiling| s.as_structure().hits() < ceiling / 2))
                     .collect::<Vec<_>>();
                 repairable.sort_by_key(|s| {
-                    if s.as_structure().structure_type() == StructureType::Rampart {
-                        (s.as_structure().hits(), 0)
-                    } else {
-                        (s.as_structure().hits(), 1)
+                    match s.as_structure().structure_type() {
+                        StructureType::Rampart => (s.as_structure().hits(), 0),
+                        StructureType::Wall => (s.as_structure().hits(), 1),
+                        _ => (s.as_structure().hits(), 2),
                     }
                 });
-                if !creep_targets.iter().any(|(name, target)| matches!(target, CreepTarget::Repair(_)) && game::creeps().values().any(|c| c.name() == name.as_str())) {
+                if !creep_targets.iter().any(|(name, target)| matches!(target, CreepTarget::Repair(_)) && live_creeps.contains(name)) {
                     if let Some(structure) = repairable.first() {
                         creep_targets.insert(name, CreepTarget::Repair(structure.pos()));
                         continue;
                     }
                 }
 
-                // upgraders
+                // with the storage overflowing, the surplus goes into the weakest wall or rampart
+                if defense::reinforcing(&room) && creep.get_active_bodyparts(Part::Work) > 0 {
+                    let weakest = room.find(find::STRUCTURES, None)
+                        .into_iter()
+                        .filter(defense::needs_reinforcing)
+                        .min_by_key(|s| s.as_structure().hits());
+                    if let Some(structure) = weakest {
+                        creep_targets.insert(name, CreepTarget::Repair(structure.pos()));
+                        continue 'creeps;
+                    }
+                }
+
+                // upgraders, which is what everyone does once there's nothing else
+                if !IDLE_UPGRADE {
+                    match energy_sink(&room) {
+                        Some(sink) => {
+                            creep_targets.insert(name, sink);
+                        }
+                        None => debug!("{}: nothing to do, holding on to its energy", name),
+                    }
+                    continue 'creeps;
+                }
+                let energy = creep.store().get_used_capacity(Some(ResourceType::Energy));
+                if energy * 100 < creep.store().get_capacity(Some(ResourceType::Energy)) * IDLE_REFILL_BELOW_PERCENT {
+                    let free = creep.store().get_free_capacity(Some(ResourceType::Energy)) as u32;
+                    let refill = room.find(find::STRUCTURES, None)
+                        .into_iter()
+                        .filter_map(|s| match s {
+                            StructureObject::StructureContainer(container) if container.store().get_used_capacity(Some(ResourceType::Energy)) >= free => Some(container),
+                            _ => None,
+                        })
+                        .min_by_key(|container| creep.pos().get_range_to(container.pos()));
+                    if let Some(container) = refill {
+                        debug!("{}: topping up before upgrading", name);
+                        creep_targets.insert(name, CreepTarget::Withdraw(container.id(), None));
+                        continue 'creeps;
+                    }
+                }
                 for structure in room.find(find::STRUCTURES, None).iter() {
                     if let StructureObject::StructureController(controller) = structure {
+                        if upgrades_paused(&room, controller) {
+                            // keep the storage reserve for rebuilding/defense, any structure
+                            // that's even slightly damaged is a better use of the energy
+                            info!("{}: upgrading paused, storage is below its {} energy reserve", name, STORAGE_UPGRADE_FLOOR);
+                            let damaged = room.find(find::STRUCTURES, None)
+                                .into_iter()
+                                .filter(defense::needs_repair)
+                                .min_by_key(|s| s.as_structure().hits());
+                            // and with nothing damaged it goes back towards the reserve
+                            if let Some(structure) = damaged {
+                                creep_targets.insert(name, CreepTarget::Repair(structure.pos()));
+                            } else if let Some(storage) = storage_drop(&room) {
+                                creep_targets.insert(name, CreepTarget::Deposit(storage));
+                            }
+                            continue 'creeps;
+                        }
                         creep_targets.insert(name, CreepTarget::Upgrade(controller.id()));
                         continue 'creeps;
                     }
                 }
             } else {
-                let has_carry = creep.body().iter().any(|body| matches!(body.part(), Part::Carry));
+                let has_carry = role.carries();
+
+                // an empty hauler takes the labs' products away and keeps them stocked with
+                // their minerals, one per room so the rest stay on energy
+                let lab_hauler = creep_targets.iter().any(|(other, target)| {
+                    matches!(target, CreepTarget::EmptyLab(_) | CreepTarget::FillLab(..))
+                        && game::creeps().get(other.clone()).is_some_and(|other| other.pos().room_name() == room.name())
+                });
+                if role.carries() && !lab_hauler && creep.store().get_used_capacity(None) == 0 {
+                    let emptying = creep_targets.values().filter_map(|target| match target {
+                        CreepTarget::EmptyLab(lab_id) => Some(*lab_id),
+                        _ => None,
+                    }).collect::<Vec<_>>();
+                    if let Some((lab_id, product)) = labs::next_product(&room, &emptying) {
+                        info!("{}: taking {:?} out of lab {}", name, product, lab_id);
+                        creep_targets.insert(name, CreepTarget::EmptyLab(lab_id));
+                        continue 'creeps;
+                    }
+                    let filling = creep_targets.values().filter_map(|target| match target {
+                        CreepTarget::FillLab(lab_id, _) => Some(*lab_id),
+                        _ => None,
+                    }).collect::<Vec<_>>();
+                    if let Some((lab_id, mineral, missing)) = labs::next_request(&room, &filling) {
+                        info!("{}: bringing {} {:?} to lab {}", name, missing, mineral, lab_id);
+                        creep_targets.insert(name, CreepTarget::FillLab(lab_id, mineral));
+                        continue 'creeps;
+                    }
+                }
+                // anything worth the walk, which is a partial load when that's all there is;
+                // the fullest get picked first below either way
+                let worth_collecting = creep.store().get_capacity(Some(ResourceType::Energy)) * PICKUP_MIN_PERCENT / 100;
+
+                // and one moves energy between the storage and the terminal, for sending and
+                // selling one way and putting away what's been sent the other
+                if role.carries() && creep.store().get_used_capacity(None) == 0 {
+                    let terminal_hauler = creep_targets.iter().any(|(other, target)| {
+                        matches!(target, CreepTarget::StockTerminal | CreepTarget::UnloadTerminal)
+                            && game::creeps().get(other.clone()).is_some_and(|other| other.pos().room_name() == room.name())
+                    });
+                    if !terminal_hauler && terminal::shortfall(&room) >= worth_collecting {
+                        info!("{}: taking energy over to the terminal", name);
+                        creep_targets.insert(name, CreepTarget::StockTerminal);
+                        continue 'creeps;
+                    } else if !terminal_hauler && terminal::surplus(&room) >= worth_collecting {
+                        info!("{}: taking energy from the terminal to storage", name);
+                        creep_targets.insert(name, CreepTarget::UnloadTerminal);
+                        continue 'creeps;
+                    }
+                }
                 let containers = room.find(find::STRUCTURES, None)
                     .into_iter()
                     .filter_map(|s| match s {
-                        StructureObject::StructureContainer(container) if container.store().get_used_capacity(Some(ResourceType::Energy)) >= creep.store().get_capacity(Some(ResourceType::Energy)) => Some(container),
+                        // the controller's container is there to be filled, not emptied again
+                        StructureObject::StructureContainer(container) if container.store().get_used_capacity(Some(ResourceType::Energy)) >= worth_collecting && !upgrading::is_controller_container(&room, &container) => Some(container),
                         _ => None
                     })
                     .collect::<Vec<_>>();
 
                 let dropped = room.find(find::DROPPED_RESOURCES, None)
                     .into_iter()
-                    .filter(|resource| resource.resource_type() == ResourceType::Energy && resource.amount() >= creep.store().get_capacity(Some(ResourceType::Energy)))
+                    .filter(|resource| resource.resource_type() == ResourceType::Energy && resource.amount() >= worth_collecting)
+                    // energy dumped by the controller is for the upgraders, anyone else picking
+                    // it back up would only go and dump it there again
+                    .filter(|resource| upgrades_with_dumped(&creep) || !room.controller().is_some_and(|controller| resource.pos().in_range_to(controller.pos(), DUMP_RANGE)))
                     .collect::<Vec<_>>();
 
                 if has_carry {
-                    if let Some(container) = containers.iter().max_by_key(|&container| container.store().get_used_capacity(Some(ResourceType::Energy))) {
-                        creep_targets.insert(name, CreepTarget::Withdraw(container.id()));
-                        return;
+                    // drain whichever container is closest to overflowing first, going by the
+                    // amount in it to break ties
+                    if let Some(container) = containers.iter().max_by_key(|&container| (mining::overflow_risk(container), container.store().get_used_capacity(Some(ResourceType::Energy)))) {
+                        creep_targets.insert(name, CreepTarget::Withdraw(container.id(), top_off_amount(&creep, &room)));
+                        continue 'creeps;
                     } else if let Some(energy) = dropped.iter().max_by_key(|&energy| energy.amount()) {
                         creep_targets.insert(name, CreepTarget::Pickup(energy.pos()));
-                        return;
+                        continue 'creeps;
+                    } else if let Some(spawn) = emergency_withdraw_spawn(&room) {
+                        info!("{}: no energy anywhere and the controller is about to downgrade, taking some from {}", name, spawn.name());
+                        creep_targets.insert(name, CreepTarget::EmergencyWithdraw(spawn.id()));
+                        continue 'creeps;
                     }
                 } else {
                     let active_sources = room.find(find::SOURCES_ACTIVE, None);
-                    let source = active_sources.iter().find(|&source| {
-                        !creep_targets.iter().any(|(name, target)| {
-                            matches!(target, CreepTarget::Harvest(id) if *id == source.id()) && game::creeps().values().any(|c| c.name() == name.as_str())
-                        })
-                    });
+                    let source = active_sources.iter().find(|&source| source_has_room(source, creep_targets));
 
                     if let Some(source) = source {
                         creep_targets.insert(name, CreepTarget::Harvest(source.id()));
-                        return;
-                    } else {
-                        creep.suicide();
+                        continue 'creeps;
+                    } else if allow_suicide() || creep.get_active_bodyparts(Part::Work) == 0 {
+                        // only a creep that couldn't mine even with a source free is no use to
+                        // anyone, the rest wait for a source to come back round
+                        warn!("{}: nothing to harvest, suiciding", name);
+                        if let Err(e) = creep.suicide() {
+                            warn!("{}: couldn't suicide: {:?}", name, e);
+                        }
                     }
                 }
 
                 if let Ok(structures) = creep.pos().look_for(look::STRUCTURES) {
                     if structures.iter().any(|s| matches!(s, StructureObject::StructureRoad(_))) {
-                        let rx: std::ops::RangeInclusive<i32> = -1..=1;
-                        'dx: for dx in rx {
-                            let ry: std::ops::RangeInclusive<i32> = -1..=1;
-                            for dy in ry {
-                                if dx == 0 && dy == 0 {
-                                    continue;
-                                }
-                                let new_pos = Position::new(RoomCoordinate::new(creep.pos().x().u8() + (dx as u8)).unwrap(), RoomCoordinate::new(creep.pos().y().u8() + (dy as u8)).unwrap(), creep.room().unwrap().name());
-                                if let Ok(structures) = new_pos.look_for(look::STRUCTURES) {
-                                    if !structures.iter().any(|s| matches!(s, StructureObject::StructureRoad(_))) {
-                                        let _ = creep.move_to(new_pos);
-                                        break 'dx;
-                                    }
-                                }
-                            }
+                        // exit tiles would walk the creep straight out of the room
+                        let off_road = movement::walkable_neighbors(creep.pos(), &room).into_iter()
+                            .filter(|pos| (1..49).contains(&pos.x().u8()) && (1..49).contains(&pos.y().u8()))
+                            .find(|pos| !pos.look_for(look::STRUCTURES).unwrap_or_default().iter().any(|s| matches!(s, StructureObject::StructureRoad(_))));
+                        if let Some(new_pos) = off_road {
+                            let _ = movement::move_to(&creep, new_pos);
                         }
                     }
                 }
@@ -567,3 +2326,93 @@ fn assign_new_targets(creep_targets: &mut HashMap<String, CreepTarget>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use screeps::local::RoomCoordinate;
+
+    fn position(x: u8, y: u8) -> Position {
+        Position::new(RoomCoordinate::new(x).unwrap(), RoomCoordinate::new(y).unwrap(), "W1N1".parse().unwrap())
+    }
+
+    #[test]
+    fn deposit_range_takes_in_the_diagonals() {
+        let structure = position(25, 25);
+        // standing on it, as on the controller container
+        assert!(position(25, 25).is_near_to(structure));
+        for (x, y) in [(24, 25), (26, 25), (25, 24), (25, 26)] {
+            assert!(position(x, y).is_near_to(structure), "orthogonal {},{}", x, y);
+        }
+        for (x, y) in [(24, 24), (26, 24), (24, 26), (26, 26)] {
+            assert!(position(x, y).is_near_to(structure), "diagonal {},{}", x, y);
+        }
+        for (x, y) in [(23, 25), (27, 27), (25, 23), (23, 24)] {
+            assert!(!position(x, y).is_near_to(structure), "range 2 {},{}", x, y);
+        }
+    }
+
+    fn count(body: &[Part], part: Part) -> usize {
+        body.iter().filter(|p| **p == part).count()
+    }
+
+    #[test]
+    fn clamp_body_leaves_bodies_that_fit() {
+        let body = [vec![Part::Work; 20], vec![Part::Carry; 10], vec![Part::Move; 20]].concat();
+        assert_eq!(clamp_body(Role::Worker, &body), body);
+        let small = vec![Part::Move, Part::Attack];
+        assert_eq!(clamp_body(Role::Defender, &small), small);
+    }
+
+    #[test]
+    fn clamp_body_cuts_down_to_the_limit() {
+        let body = [vec![Part::Work; 30], vec![Part::Carry; 15], vec![Part::Move; 30]].concat();
+        let clamped = clamp_body(Role::Worker, &body);
+        assert_eq!(clamped.len(), MAX_CREEP_SIZE as usize);
+        for part in [Part::Work, Part::Carry, Part::Move] {
+            assert!(count(&clamped, part) <= count(&body, part));
+        }
+    }
+
+    #[test]
+    fn clamp_body_keeps_one_of_each_part() {
+        let body = [vec![Part::Tough], vec![Part::Attack; 60], vec![Part::Heal], vec![Part::Move; 40]].concat();
+        let clamped = clamp_body(Role::Defender, &body);
+        assert!(clamped.len() <= MAX_CREEP_SIZE as usize);
+        for part in [Part::Tough, Part::Attack, Part::Heal, Part::Move] {
+            assert!(count(&clamped, part) >= 1, "lost every {:?}", part);
+        }
+        // still in the body's own order, TOUGH in front to take the first hits
+        assert_eq!(clamped.first(), Some(&Part::Tough));
+    }
+
+    #[test]
+    fn dead_creeps_without_creep_memory() {
+        let live = HashSet::from(["harv-1".to_string()]);
+        assert!(dead_creeps(None, &live).is_empty());
+        assert!(dead_creeps(Some(Vec::new()), &live).is_empty());
+    }
+
+    #[test]
+    fn dead_creeps_skips_keys_that_arent_names() {
+        let live = HashSet::from(["harv-1".to_string()]);
+        let keys = vec![Some("harv-1".to_string()), None, Some("work-2".to_string())];
+        assert_eq!(dead_creeps(Some(keys), &live), vec!["work-2".to_string()]);
+    }
+
+    #[test]
+    fn sight_keeps_targets_that_cant_be_looked_at() {
+        assert_eq!(sight(Err::<Vec<u32>, _>(()), |_| true), Sighting::OutOfSight);
+    }
+
+    #[test]
+    fn sight_clears_targets_with_nothing_there() {
+        assert_eq!(sight(Ok::<Vec<u32>, ()>(Vec::new()), |_| true), Sighting::Gone);
+        assert_eq!(sight(Ok::<_, ()>(vec![1, 3]), |n| *n == 2), Sighting::Gone);
+    }
+
+    #[test]
+    fn sight_finds_the_first_wanted() {
+        assert_eq!(sight(Ok::<_, ()>(vec![1, 2, 3, 2]), |n| *n >= 2), Sighting::Found(2));
+    }
+}
@@ -0,0 +1,103 @@
+use log::*;
+use screeps::{
+    constants::{find, Part, ResourceType},
+    enums::StructureObject,
+    local::Position,
+    objects::{Creep, Room, Source, StructureController, StructureLink},
+    prelude::*,
+};
+
+// source links only fire once they've built up this much, since every transfer loses 3% and
+// puts the link on cooldown
+const SOURCE_LINK_SEND_THRESHOLD: u32 = 400;
+
+fn link_near(pos: Position, range: u8) -> Option<StructureLink> {
+    pos.find_in_range(find::MY_STRUCTURES, range)
+        .into_iter()
+        .find_map(|s| match s {
+            StructureObject::StructureLink(link) => Some(link),
+            _ => None,
+        })
+}
+
+// a link close enough to the controller for upgraders to work from, if the room has one
+pub fn controller_link(controller: &StructureController) -> Option<StructureLink> {
+    link_near(controller.pos(), 2)
+}
+
+// a link next to a source's mining spot(s), for the miners to drop their energy straight into
+pub fn source_link(source: &Source) -> Option<StructureLink> {
+    link_near(source.pos(), 2)
+}
+
+// a link next to the storage, which carriers fill to feed the controller link
+pub fn storage_link(room: &Room) -> Option<StructureLink> {
+    link_near(room.storage()?.pos(), 2)
+}
+
+pub fn has_source_links(room: &Room) -> bool {
+    room.find(find::SOURCES, None).iter().any(|source| source_link(source).is_some())
+}
+
+// a miner with CARRY parts next to its source's link empties into it once it's nearly full,
+// so the energy gets beamed to where it's needed instead of piling up in the container;
+// transferring doesn't get in the way of harvesting on the same tick, so this isn't counted
+// as the creep's action
+pub fn fill_source_link(creep: &Creep, source: &Source) {
+    if creep.get_active_bodyparts(Part::Carry) == 0 {
+        return;
+    }
+    let harvest_per_tick = creep.get_active_bodyparts(Part::Work) as u32 * 2;
+    if creep.store().get_free_capacity(Some(ResourceType::Energy)) as u32 > harvest_per_tick {
+        return;
+    }
+    if let Some(link) = source_link(source) {
+        if creep.pos().is_near_to(link.pos()) && link.store().get_free_capacity(Some(ResourceType::Energy)) > 0 {
+            let _ = creep.transfer(&link, ResourceType::Energy, None);
+        }
+    }
+}
+
+// send energy on from the links at sources: to the controller link when it has room for it,
+// otherwise to any other link in the room that isn't at a source (storage or hub links); and
+// from the storage link on to the controller link, so the upgraders never run dry
+pub fn run_links(room: &Room) {
+    let sources = room.find(find::SOURCES, None);
+    let controller_link = room.controller().and_then(|controller| controller_link(&controller));
+    let links = room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .filter_map(|s| match s {
+            StructureObject::StructureLink(link) => Some(link),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let (source_links, other_links): (Vec<_>, Vec<_>) = links
+        .into_iter()
+        .partition(|link| sources.iter().any(|source| source.pos().in_range_to(link.pos(), 2)));
+
+    for link in source_links {
+        if link.cooldown() > 0 || link.store().get_used_capacity(Some(ResourceType::Energy)) < SOURCE_LINK_SEND_THRESHOLD {
+            continue;
+        }
+        let receiver = controller_link.iter()
+            .chain(other_links.iter())
+            .find(|receiver| receiver.store().get_free_capacity(Some(ResourceType::Energy)) > 0);
+        if let Some(receiver) = receiver {
+            match link.transfer_energy(receiver, None) {
+                Ok(()) => debug!("link at {:?} sending energy to {:?}", link.pos(), receiver.pos()),
+                Err(e) => warn!("couldn't send link energy: {:?}", e),
+            }
+        }
+    }
+
+    if let (Some(storage_link), Some(controller_link)) = (storage_link(room), controller_link.as_ref()) {
+        let available = storage_link.store().get_used_capacity(Some(ResourceType::Energy));
+        let room_for = controller_link.store().get_free_capacity(Some(ResourceType::Energy)).max(0) as u32;
+        if storage_link.cooldown() == 0 && available > 0 && room_for > 0 && storage_link.id() != controller_link.id() {
+            match storage_link.transfer_energy(controller_link, Some(available.min(room_for))) {
+                Ok(()) => debug!("storage link sending energy to the controller link"),
+                Err(e) => warn!("couldn't send storage link energy: {:?}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use js_sys::{JsString, Object, Reflect};
+use log::*;
+use screeps::{
+    constants::{MarketResourceType, OrderType, ResourceType, MARKET_FEE},
+    game,
+    objects::Room,
+    prelude::*,
+};
+use wasm_bindgen::JsValue;
+
+use crate::memory;
+
+// selling off surplus energy: a room whose storage has been sitting above
+// `STORAGE_SELL_THRESHOLD` for a few checks in a row puts a sell order up for the surplus, at
+// roughly the going rate, and keeps that order's price in line with the market afterwards.
+// what the order still has to deliver is kept as `Memory.rooms[name].energyForSale`, so the
+// haulers know how much to bring over from the storage to the terminal it's sold out of
+
+// storage energy above this is surplus; rooms check how much they have every so often, as
+// `schedule::Pass::Market`
+const STORAGE_SELL_THRESHOLD: u32 = 800_000;
+
+// the storage has to be over the threshold for this many checks running before anything gets
+// sold, so a brief spike doesn't send energy the room will want back off to market
+const HIGH_CHECKS_BEFORE_SELLING: u32 = 3;
+
+// at most this much energy goes up for sale in one order
+const SELL_BATCH: u32 = 50_000;
+
+// never sell for less than this many credits per unit, however low the market goes
+const MIN_ENERGY_PRICE: f64 = 0.05;
+
+// an order whose price is this far off the market (as a fraction) gets repriced
+const REPRICE_FRACTION: f64 = 0.1;
+
+// the average price energy went for most recently, or None with no market history to go by
+fn market_price() -> Option<f64> {
+    game::market::get_history(Some(ResourceType::Energy))
+        .last()
+        .map(|record| record.avg_price())
+}
+
+// the energy the room's sell order still has to deliver out of the terminal
+pub fn energy_for_sale(room: &Room) -> u32 {
+    memory::get_u32(&memory::room(room.name()), "energyForSale").unwrap_or(0)
+}
+
+fn current_price() -> Option<f64> {
+    market_price().map(|price| price.max(MIN_ENERGY_PRICE))
+}
+
+// bring the room's sell order into line with the market, cancelling it once it's done; false
+// if the room has no order up
+fn keep_up_order(room: &Room, price: f64) -> bool {
+    let room_memory = memory::room(room.name());
+    let room_name = JsString::from(room.name().to_string());
+    let existing = game::market::orders().values().find(|order| {
+        order.order_type() == OrderType::Sell
+            && order.resource_type() == MarketResourceType::Resource(ResourceType::Energy)
+            && order.room_name().as_ref() == Some(&room_name)
+    });
+    let Some(order) = existing else {
+        memory::set_u32(&room_memory, "energyForSale", 0);
+        return false;
+    };
+    memory::set_u32(&room_memory, "energyForSale", order.remaining_amount());
+    if order.remaining_amount() == 0 {
+        info!("{}: energy sell order {} is done, cancelling it", room.name(), String::from(order.id()));
+        let _ = game::market::cancel_order(&order.id());
+    } else if (order.price() - price).abs() > order.price() * REPRICE_FRACTION {
+        info!("{}: repricing energy sell order from {:.3} to {:.3}", room.name(), order.price(), price);
+        if let Err(e) = game::market::change_order_price(&order.id(), price) {
+            warn!("couldn't reprice energy sell order: {:?}", e);
+        }
+    }
+    true
+}
+
+// keep the room's sell order priced in between the regular checks, which is all the spare CPU
+// goes on with `Memory.bucketSurplus = "market"`; only the regular checks count towards putting
+// a new order up
+pub fn keep_order_priced(room: &Room) {
+    if let Some(price) = current_price() {
+        keep_up_order(room, price);
+    }
+}
+
+pub fn run_market(room: &Room) {
+    let (Some(storage), Some(_terminal)) = (room.storage(), room.terminal()) else {
+        return;
+    };
+    if !storage.my() {
+        return;
+    }
+    let room_memory = memory::room(room.name());
+    let stored = storage.store().get_used_capacity(Some(ResourceType::Energy));
+    let high_checks = if stored > STORAGE_SELL_THRESHOLD {
+        memory::get_u32(&room_memory, "energyHigh").unwrap_or(0) + 1
+    } else {
+        0
+    };
+    memory::set_u32(&room_memory, "energyHigh", high_checks);
+
+    let Some(price) = current_price() else {
+        debug!("{}: no energy market history to price an order from", room.name());
+        return;
+    };
+
+    // keep up any order we already have for this room, and don't stack another on top of it
+    if keep_up_order(room, price) {
+        return;
+    }
+
+    if high_checks < HIGH_CHECKS_BEFORE_SELLING {
+        return;
+    }
+    let amount = (stored - STORAGE_SELL_THRESHOLD).min(SELL_BATCH);
+    // creating an order costs a fee up front, out of the credits we have
+    let fee = price * amount as f64 * MARKET_FEE as f64;
+    if game::market::credits() < fee {
+        debug!("{}: can't afford the {:.0} credit fee to sell energy", room.name(), fee);
+        return;
+    }
+
+    let order = Object::new();
+    let _ = Reflect::set(&order, &JsString::from("type"), &JsString::from("sell"));
+    let _ = Reflect::set(&order, &JsString::from("resourceType"), &JsString::from("energy"));
+    let _ = Reflect::set(&order, &JsString::from("price"), &JsValue::from(price));
+    let _ = Reflect::set(&order, &JsString::from("totalAmount"), &JsValue::from(amount));
+    let _ = Reflect::set(&order, &JsString::from("roomName"), &JsString::from(room.name().to_string()));
+    match game::market::create_order(&order) {
+        Ok(()) => {
+            warn!("{}: selling {} surplus energy at {:.3}", room.name(), amount, price);
+            memory::set_u32(&room_memory, "energyForSale", amount);
+        }
+        Err(e) => warn!("couldn't create energy sell order: {:?}", e),
+    }
+}
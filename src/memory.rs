@@ -0,0 +1,77 @@
+use js_sys::{JsString, Object, Reflect};
+use screeps::local::RoomName;
+use wasm_bindgen::{JsCast, JsValue};
+
+// small helpers for poking at plain objects stored in the `Memory` global; anything written
+// here is serialized by the game at the end of the tick, so keep the values simple
+
+// get the object stored under `key`, creating (and storing) an empty one if it's missing or
+// isn't an object
+pub fn child(parent: &Object, key: &str) -> Object {
+    let key = JsString::from(key);
+    match Reflect::get(parent, &key) {
+        Ok(value) if value.is_object() => value.unchecked_into(),
+        _ => {
+            let value = Object::new();
+            let _ = Reflect::set(parent, &key, &value);
+            value
+        }
+    }
+}
+
+// `Memory.rooms[name]`, where everything kept about a room lives
+pub fn room(name: RoomName) -> Object {
+    child(&child(&screeps::memory::ROOT, "rooms"), &name.to_string())
+}
+
+// a number from memory as a u32; anything missing, that isn't a number, or that's negative
+// or not a number at all (NaN) is None
+fn as_u32(value: Option<f64>) -> Option<u32> {
+    value.filter(|value| *value >= 0.0).map(|value| value as u32)
+}
+
+pub fn get_u32(parent: &Object, key: &str) -> Option<u32> {
+    as_u32(Reflect::get(parent, &JsString::from(key)).ok().and_then(|value| value.as_f64()))
+}
+
+pub fn set_u32(parent: &Object, key: &str, value: u32) {
+    let _ = Reflect::set(parent, &JsString::from(key), &JsValue::from(value));
+}
+
+pub fn get_string(parent: &Object, key: &str) -> Option<String> {
+    Reflect::get(parent, &JsString::from(key))
+        .ok()
+        .and_then(|value| value.as_string())
+}
+
+pub fn set_string(parent: &Object, key: &str, value: &str) {
+    let _ = Reflect::set(parent, &JsString::from(key), &JsString::from(value));
+}
+
+// the keys of a plain object, skipping anything that isn't a string
+pub fn keys(parent: &Object) -> Vec<String> {
+    Object::keys(parent).iter().filter_map(|key| key.as_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_or_wrong_type_is_none() {
+        assert_eq!(as_u32(None), None);
+    }
+
+    #[test]
+    fn bad_numbers_are_none() {
+        assert_eq!(as_u32(Some(f64::NAN)), None);
+        assert_eq!(as_u32(Some(-1.0)), None);
+    }
+
+    #[test]
+    fn numbers_are_read() {
+        assert_eq!(as_u32(Some(0.0)), Some(0));
+        assert_eq!(as_u32(Some(1500.0)), Some(1500));
+        assert_eq!(as_u32(Some(2.7)), Some(2));
+    }
+}
@@ -0,0 +1,177 @@
+use log::*;
+use screeps::{
+    constants::{find, Part, ResourceType, ENERGY_REGEN_TIME, HARVEST_POWER},
+    enums::StructureObject,
+    game,
+    local::{ObjectId, Position},
+    objects::{Creep, Room, Source, StructureContainer},
+    prelude::*,
+};
+
+use crate::{memory, movement, planner};
+
+fn source_memory(source: &Source) -> js_sys::Object {
+    memory::child(&memory::child(&screeps::memory::ROOT, "sources"), &source.id().to_string())
+}
+
+// how many WORK parts it takes to empty a source before it regenerates (each harvests 2 a
+// tick, so 5 for a normal 3000 energy source), plus one more if the last cycle ended with
+// energy left in it anyway, to make up for the time lost walking out and between miners
+pub fn work_parts_needed(source: &Source) -> u32 {
+    let saturating = source.energy_capacity().div_ceil(ENERGY_REGEN_TIME * HARVEST_POWER);
+    let under_mined = memory::get_u32(&source_memory(source), "underMined").unwrap_or(0) > 0;
+    saturating + under_mined as u32
+}
+
+// energy lying around a source (dropped, or in its container) beyond this fraction of the
+// source's capacity means the carriers aren't keeping up, and more WORK won't help
+const BACKLOG_PERCENT: u32 = 50;
+
+// at the end of each regeneration cycle, note whether the source was mined dry; a source with
+// energy left over and nothing piling up around it needs bigger miners, while one with energy
+// piling up needs more carriers instead. kept in `Memory.sources[id].underMined`, along with
+// what was left over last time as `leftover`
+pub fn track_sources(room: &Room) {
+    for source in room.find(find::SOURCES, None) {
+        if source.ticks_to_regeneration() != Some(1) {
+            continue;
+        }
+        let source_memory = source_memory(&source);
+        let leftover = source.energy();
+        let dropped: u32 = source.pos().find_in_range(find::DROPPED_RESOURCES, 1)
+            .iter()
+            .filter(|resource| resource.resource_type() == ResourceType::Energy)
+            .map(|resource| resource.amount())
+            .sum();
+        let contained: u32 = source.pos().find_in_range(find::STRUCTURES, 1)
+            .iter()
+            .filter_map(|s| match s {
+                StructureObject::StructureContainer(container) => Some(container.store().get_used_capacity(Some(ResourceType::Energy))),
+                _ => None,
+            })
+            .sum();
+        let backlog = (dropped + contained) * 100 > source.energy_capacity() * BACKLOG_PERCENT;
+        if backlog {
+            info!("{}: {} energy piling up at source {}, carriers are the bottleneck", room.name(), dropped + contained, source.id());
+        } else if leftover > 0 {
+            info!("{}: source {} regenerated with {} energy still in it, sizing up its miners", room.name(), source.id(), leftover);
+        }
+        memory::set_u32(&source_memory, "leftover", leftover);
+        memory::set_u32(&source_memory, "underMined", (leftover > 0 && !backlog) as u32);
+    }
+}
+
+// a static miner with `work` WORK parts, or as many as `energy` pays for, with a MOVE for
+// every two (it only walks out to the source once) and a CARRY if it's to fill a link
+pub fn harvester_body(energy: u32, work: u32, carry: bool) -> Vec<Part> {
+    let cost = |work: u32| work * Part::Work.cost() + work.div_ceil(2) * Part::Move.cost() + if carry { Part::Carry.cost() } else { 0 };
+    let work = (1..=work).rev().find(|&work| cost(work) <= energy).unwrap_or(0);
+    if work == 0 {
+        return Vec::new();
+    }
+    let mut body = vec![Part::Move; work.div_ceil(2) as usize];
+    if carry {
+        body.push(Part::Carry);
+    }
+    body.extend(vec![Part::Work; work as usize]);
+    body
+}
+
+// miners with CARRY parts repair the container they're standing on once it drops below this
+// percentage of its hits
+const CONTAINER_REPAIR_BELOW_PERCENT: u32 = 50;
+
+// a source with no container is only looked at again for a new one this often, in ticks, and
+// the tiles around it counted again
+const CONTAINER_RESCAN_TICKS: u32 = 100;
+
+// the container next to a source, which miners look for every tick; its id is kept in
+// `Memory.sources[id].container` so it can be resolved directly instead of searching around
+// the source each time, and looked for again once it's destroyed (or, for sources without
+// one, every `CONTAINER_RESCAN_TICKS` in case one's been built). where it stood is kept as
+// `Memory.sources[id].containerPos`, so a destroyed one gets a site to rebuild it in the same place
+pub fn source_container(source: &Source) -> Option<StructureContainer> {
+    let source_memory = source_memory(source);
+    let cached = memory::get_string(&source_memory, "container")
+        .and_then(|id| id.parse::<ObjectId<StructureContainer>>().ok());
+    if let Some(container) = cached.and_then(|id| id.resolve()) {
+        return Some(container);
+    }
+    let lost = cached.is_some();
+    let checked = memory::get_u32(&source_memory, "containerChecked");
+    if !lost && checked.is_some_and(|checked| game::time() < checked + CONTAINER_RESCAN_TICKS) {
+        return None;
+    }
+
+    let container = source.pos().find_in_range(find::STRUCTURES, 1)
+        .into_iter()
+        .find_map(|s| match s {
+            StructureObject::StructureContainer(container) => Some(container),
+            _ => None,
+        });
+    memory::set_string(&source_memory, "container", &container.as_ref().map_or(String::new(), |container| container.id().to_string()));
+    memory::set_u32(&source_memory, "containerChecked", game::time());
+    match &container {
+        Some(container) => memory::set_u32(&source_memory, "containerPos", container.pos().packed_repr()),
+        None if lost => {
+            let pos = memory::get_u32(&source_memory, "containerPos").map(Position::from_packed);
+            warn!("{}: lost the container at source {} ({:?})", source.pos().room_name(), source.id(), pos);
+            if let Some(room) = source.room() {
+                planner::rebuild_container(&room, pos);
+            }
+        }
+        None => {}
+    }
+    container
+}
+
+// the container under a miner, if it's worn down enough to repair and the miner has the
+// energy to do it with; miners without CARRY never hold any energy, so they never repair
+pub fn container_needing_repair<'a>(creep: &Creep, container: &'a StructureContainer) -> Option<&'a dyn Repairable> {
+    if creep.get_active_bodyparts(Part::Carry) == 0 {
+        return None;
+    }
+    // a repair costs one energy per WORK part
+    if creep.store().get_used_capacity(Some(ResourceType::Energy)) < creep.get_active_bodyparts(Part::Work) as u32 {
+        return None;
+    }
+    if container.hits() * 100 >= container.hits_max() * CONTAINER_REPAIR_BELOW_PERCENT {
+        return None;
+    }
+    Some(container)
+}
+
+// how much being actively mined into adds to a container's overflow risk, in percentage points
+// of fill; a container a miner is filling overflows long before an idle one with a bit more
+const MINED_OVERFLOW_RISK: u32 = 25;
+
+// how close a container is to overflowing and wasting energy: how full it is, as a percentage,
+// bumped up if there's a miner next to it (or on it) putting more in every tick
+pub fn overflow_risk(container: &StructureContainer) -> u32 {
+    let store = container.store();
+    let capacity = store.get_capacity(Some(ResourceType::Energy)).max(1);
+    let fill = store.get_used_capacity(Some(ResourceType::Energy)) * 100 / capacity;
+    let mined = container.pos().find_in_range(find::MY_CREEPS, 1).iter().any(|creep| creep.get_active_bodyparts(Part::Work) > 0 && creep.get_active_bodyparts(Part::Carry) == 0);
+    if mined { fill + MINED_OVERFLOW_RISK } else { fill }
+}
+
+// the number of walkable tiles around a source, i.e. how many harvesters can physically mine
+// it at once; this hardly ever changes, so it's kept in `Memory.sources[id].tiles` and only
+// counted again every `CONTAINER_RESCAN_TICKS` (as of `tilesAt`), in case something's been
+// built on or cleared from around it
+pub fn open_tiles(source: &Source) -> u32 {
+    let source_memory = source_memory(source);
+    let counted_at = memory::get_u32(&source_memory, "tilesAt");
+    if let Some(tiles) = memory::get_u32(&source_memory, "tiles") {
+        if counted_at.is_some_and(|at| game::time() < at + CONTAINER_RESCAN_TICKS) {
+            return tiles;
+        }
+    }
+
+    let room = source.room().expect("couldn't resolve source room");
+    let tiles = movement::walkable_neighbors(source.pos(), &room).len() as u32;
+
+    memory::set_u32(&source_memory, "tiles", tiles);
+    memory::set_u32(&source_memory, "tilesAt", game::time());
+    tiles
+}
@@ -0,0 +1,234 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{
+    constants::{find, look, ErrorCode, ExitDirection, StructureType, Terrain},
+    enums::StructureObject,
+    game::{self, map::FindRouteOptions},
+    local::{Position, RoomName},
+    objects::{CostMatrix, Creep, MoveToOptions, Room},
+    pathfinder::SingleRoomCostResult,
+    prelude::*,
+};
+
+use crate::intents;
+
+// where each moving creep was when it last tried to move, the tick it tried, and how many
+// ticks in a row it's tried without getting anywhere
+type Attempts = HashMap<String, (Position, u32, u32)>;
+
+// the creeps' attempts, alongside the tick dead creeps were last cleared out
+thread_local! {
+    static STUCK: RefCell<(u32, Attempts)> = RefCell::new((0, HashMap::new()));
+}
+
+// the walkable tiles around each position asked about this tick, alongside the tick they were
+// worked out on
+thread_local! {
+    static NEIGHBORS: RefCell<(u32, HashMap<Position, Vec<Position>>)> = RefCell::new((0, HashMap::new()));
+}
+
+// a creep that's tried to move this many ticks in a row without going anywhere is stuck
+// behind other creeps, and gets a fresh path around them instead of its cached one
+const STUCK_TICKS: u32 = 3;
+
+// send creeps through source keeper rooms when routing? keepers will kill anything that isn't
+// built to fight them, so only turn this off for creeps that are
+const AVOID_SOURCE_KEEPER_ROOMS: bool = true;
+
+// source keeper rooms are the ring of rooms around each sector's center room, i.e. those
+// whose displayed coordinates both end in 4-6, except for the 5/5 center itself
+pub fn is_source_keeper_room(name: RoomName) -> bool {
+    // `x_coord`/`y_coord` count W and N rooms from -1, undo that to get the displayed number
+    let displayed = |coord: i32| if coord < 0 { -coord - 1 } else { coord };
+    let (x, y) = (displayed(name.x_coord()) % 10, displayed(name.y_coord()) % 10);
+    (4..=6).contains(&x) && (4..=6).contains(&y) && !(x == 5 && y == 5)
+}
+
+// how many ticks in a row the creep has tried to move from where it's standing now; only
+// consecutive ticks count, a creep that stopped to work for a while and then sets off again
+// from the same spot isn't stuck
+fn stuck_ticks(creep: &Creep) -> u32 {
+    STUCK.with(|stuck_refcell| {
+        let mut stuck = stuck_refcell.borrow_mut();
+        let now = game::time();
+        if stuck.0 != now {
+            stuck.0 = now;
+            stuck.1.retain(|_, (_, tick, _)| *tick + 1 >= now);
+        }
+        let pos = creep.pos();
+        let entry = stuck.1.entry(creep.name()).or_insert((pos, now, 0));
+        let count = if entry.0 == pos && entry.1 + 1 == now { entry.2 + 1 } else if entry.1 == now { entry.2 } else { 0 };
+        *entry = (pos, now, count);
+        count
+    })
+}
+
+// structures that creeps can't stand on; roads, containers and our own ramparts are passable
+pub fn is_obstacle(structure: &StructureObject) -> bool {
+    match structure {
+        StructureObject::StructureRoad(_) | StructureObject::StructureContainer(_) => false,
+        StructureObject::StructureRampart(rampart) => !rampart.my(),
+        _ => true,
+    }
+}
+
+// the tiles next to `pos` a creep could stand on, going by the terrain and the structures on
+// them; only tiles in `room` itself count, so a position on the edge of the room has fewer of
+// them. structures change, so this is only kept for the rest of the tick
+pub fn walkable_neighbors(pos: Position, room: &Room) -> Vec<Position> {
+    NEIGHBORS.with(|neighbors_refcell| {
+        let mut neighbors = neighbors_refcell.borrow_mut();
+        let now = game::time();
+        if neighbors.0 != now {
+            *neighbors = (now, HashMap::new());
+        }
+        neighbors.1.entry(pos).or_insert_with(|| {
+            let terrain = room.get_terrain();
+            neighbors_where(pos, |neighbor| {
+                terrain.get(neighbor.x().u8(), neighbor.y().u8()) != Terrain::Wall
+                    && !neighbor.look_for(look::STRUCTURES).unwrap_or_default().iter().any(is_obstacle)
+            })
+        }).clone()
+    })
+}
+
+// the tiles next to `pos` in its own room that pass `walkable`
+fn neighbors_where(pos: Position, walkable: impl Fn(Position) -> bool) -> Vec<Position> {
+    let mut neighbors = Vec::new();
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            if dx == 0 && dy == 0 {
+                continue;
+            }
+            let Ok(neighbor) = pos.checked_add((dx, dy)) else {
+                continue;
+            };
+            if neighbor.room_name() == pos.room_name() && walkable(neighbor) {
+                neighbors.push(neighbor);
+            }
+        }
+    }
+    neighbors
+}
+
+// what pathing through a construction site costs, by what it'll become; our own sites are
+// walkable, but a creep that paths across a wall site can find it's been built into a wall
+// behind it (and a creep standing on one stops it being built at all), while finished
+// ramparts are only walkable for us, so it's best not to get in the habit of crossing those
+// either. anything else is left to the default costs
+fn site_cost(structure_type: StructureType) -> Option<u8> {
+    match structure_type {
+        StructureType::Wall => Some(255),
+        StructureType::Rampart => Some(10),
+        _ => None,
+    }
+}
+
+// the cost callback for pathing within a room, which steers around the construction sites
+// that are going to get in the way when they're finished
+fn avoid_blocking_sites(room_name: RoomName, matrix: CostMatrix) -> SingleRoomCostResult {
+    let Some(room) = game::rooms().get(room_name) else {
+        return SingleRoomCostResult::Default;
+    };
+    let mut changed = false;
+    for site in room.find(find::MY_CONSTRUCTION_SITES, None) {
+        if let Some(cost) = site_cost(site.structure_type()) {
+            matrix.set(site.pos().x().u8(), site.pos().y().u8(), cost);
+            changed = true;
+        }
+    }
+    if changed {
+        SingleRoomCostResult::CostMatrix(matrix)
+    } else {
+        SingleRoomCostResult::Default
+    }
+}
+
+// step towards a target in the creep's room, repathing around whatever's in the way if the
+// creep has been stuck for a while
+fn step_towards<T: HasPosition>(creep: &Creep, target: T) -> Result<(), ErrorCode> {
+    let options = if stuck_ticks(creep) >= STUCK_TICKS {
+        debug!("{}: stuck at {:?}, repathing", creep.name(), creep.pos());
+        MoveToOptions::new().reuse_path(0).ignore_creeps(false)
+    } else {
+        MoveToOptions::new()
+    };
+    creep.move_to_with_options(target, Some(options.cost_callback(avoid_blocking_sites)))
+}
+
+// all creep movement goes through here (or `travel_to`) so that a creep never issues more
+// than one move per tick
+pub fn move_to<T: HasPosition>(creep: &Creep, target: T) -> Result<(), ErrorCode> {
+    if !intents::claim_move(creep) {
+        return Err(ErrorCode::Busy);
+    }
+    step_towards(creep, target)
+}
+
+// move a creep towards `target`, which is allowed to be in another room; targets in the
+// creep's room get a plain `move_to`, anything further away is travelled to room by room
+// along `game::map::find_route` (steering clear of source keeper rooms), heading for the exit
+// into the next room of the route (which is recomputed from wherever the creep ends up each
+// tick)
+pub fn travel_to(creep: &Creep, target: Position) -> Result<(), ErrorCode> {
+    if !intents::claim_move(creep) {
+        return Err(ErrorCode::Busy);
+    }
+    let current = creep.pos().room_name();
+    if current == target.room_name() {
+        return step_towards(creep, target);
+    }
+
+    // keep out of source keeper rooms along the way, unless that's where we're going
+    let destination = target.room_name();
+    let options = FindRouteOptions::new().room_callback(move |to_room: RoomName, _from_room: RoomName| {
+        if AVOID_SOURCE_KEEPER_ROOMS && to_room != destination && is_source_keeper_room(to_room) {
+            f64::INFINITY
+        } else {
+            1.0
+        }
+    });
+    let route = game::map::find_route(current, destination, Some(options))?;
+    let step = route.first().ok_or(ErrorCode::NoPath)?;
+    let exit = match step.exit {
+        ExitDirection::Top => creep.pos().find_closest_by_range(find::EXIT_TOP),
+        ExitDirection::Right => creep.pos().find_closest_by_range(find::EXIT_RIGHT),
+        ExitDirection::Bottom => creep.pos().find_closest_by_range(find::EXIT_BOTTOM),
+        ExitDirection::Left => creep.pos().find_closest_by_range(find::EXIT_LEFT),
+    };
+    match exit {
+        Some(exit) => step_towards(creep, exit),
+        None => Err(ErrorCode::NoPath),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use screeps::local::RoomCoordinate;
+
+    fn position(x: u8, y: u8) -> Position {
+        Position::new(RoomCoordinate::new(x).unwrap(), RoomCoordinate::new(y).unwrap(), "W1N1".parse().unwrap())
+    }
+
+    #[test]
+    fn neighbors_in_the_open() {
+        assert_eq!(neighbors_where(position(25, 25), |_| true).len(), 8);
+    }
+
+    #[test]
+    fn neighbors_stop_at_the_edge_of_the_room() {
+        let corner = neighbors_where(position(0, 0), |_| true);
+        assert_eq!(corner.len(), 3);
+        assert!(corner.iter().all(|neighbor| neighbor.room_name() == position(0, 0).room_name()));
+        assert_eq!(neighbors_where(position(49, 25), |_| true).len(), 5);
+    }
+
+    #[test]
+    fn neighbors_walled_in() {
+        assert!(neighbors_where(position(25, 25), |_| false).is_empty());
+        let gap = position(24, 26);
+        assert_eq!(neighbors_where(position(25, 25), |neighbor| neighbor == gap), vec![gap]);
+    }
+}
@@ -0,0 +1,423 @@
+use std::cell::RefCell;
+
+use log::*;
+use screeps::{
+    constants::{find, look, ErrorCode, Terrain, MAX_CONSTRUCTION_SITES},
+    local::{Position, RoomCoordinate},
+    game,
+    objects::Room,
+    structure::StructureType,
+    prelude::*,
+};
+
+use crate::{memory, stamp, upgrading};
+
+// the number of construction sites we have across all rooms, counted once per tick and
+// bumped as planners place more, alongside the tick it was counted on
+thread_local! {
+    static SITE_COUNT: RefCell<Option<(u32, u32)>> = const { RefCell::new(None) };
+}
+
+// which planners get first call on the global construction site allowance; lower priorities
+// stop short of the cap so there's always some left over for the more important ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SitePriority {
+    Defense,
+    Economy,
+    Roads,
+}
+
+impl SitePriority {
+    pub fn of(structure_type: StructureType) -> SitePriority {
+        match structure_type {
+            // a lost spawn is the one thing we can't recover from without building it again
+            StructureType::Spawn | StructureType::Tower | StructureType::Rampart | StructureType::Wall => SitePriority::Defense,
+            StructureType::Road => SitePriority::Roads,
+            _ => SitePriority::Economy,
+        }
+    }
+
+    fn site_limit(self) -> u32 {
+        match self {
+            SitePriority::Defense => MAX_CONSTRUCTION_SITES,
+            SitePriority::Economy => MAX_CONSTRUCTION_SITES - 10,
+            SitePriority::Roads => MAX_CONSTRUCTION_SITES - 25,
+        }
+    }
+}
+
+// every construction site we place goes through here, so planners can't collectively run
+// into the global site cap; fails with `ErrorCode::Full` once the budget for the structure's
+// priority is used up
+pub fn place_site(room: &Room, pos: Position, structure_type: StructureType) -> Result<(), ErrorCode> {
+    SITE_COUNT.with(|site_count_refcell| {
+        let mut site_count = site_count_refcell.borrow_mut();
+        let count = match *site_count {
+            Some((tick, count)) if tick == game::time() => count,
+            _ => game::construction_sites().values().count() as u32,
+        };
+        if count >= SitePriority::of(structure_type).site_limit() {
+            *site_count = Some((game::time(), count));
+            return Err(ErrorCode::Full);
+        }
+
+        let result = room.create_construction_site(pos.x().u8(), pos.y().u8(), structure_type, None);
+        *site_count = Some((game::time(), if result.is_ok() { count + 1 } else { count }));
+        result
+    })
+}
+
+// how far out from the spawn we're willing to look for building spots
+const PLAN_RADIUS: i32 = 8;
+
+// compare the controller level against the one we saw last and re-plan the room when it
+// changes; the level is kept in `Memory.rooms[name].rcl`, so a missing value (fresh memory
+// or a newly claimed room) counts as a change too
+pub fn check_controller_level(room: &Room) {
+    let controller = match room.controller() {
+        Some(controller) if controller.my() => controller,
+        _ => return,
+    };
+    let level = controller.level() as u32;
+    let room_memory = memory::room(room.name());
+    let previous = memory::get_u32(&room_memory, "rcl");
+    if previous == Some(level) {
+        // extensions held back for the source containers get planned once they're built
+        if memory::get_u32(&room_memory, "extensionsDeferred").unwrap_or(0) > 0 && sources_have_containers(room) {
+            info!("{}: source containers are up, planning extensions", room.name());
+            memory::set_u32(&room_memory, "extensionsDeferred", 0);
+            plan_room(room, level);
+        }
+        return;
+    }
+
+    match previous {
+        Some(previous) if previous < level => warn!("{} reached controller level {}!", room.name(), level),
+        Some(previous) => warn!("{} dropped from controller level {} to {}!", room.name(), previous, level),
+        None => info!("{} is at controller level {}", room.name(), level),
+    }
+    memory::set_u32(&room_memory, "rcl", level);
+    plan_room(room, level);
+}
+
+// when no spawn site can be placed, the room waits this long before trying again, doubling each
+// time it fails again up to `SPAWN_RETRY_MAX_TICKS`
+const SPAWN_RETRY_TICKS: u32 = 10;
+const SPAWN_RETRY_MAX_TICKS: u32 = 1000;
+
+// an owned room that's lost all its spawns can't make any more creeps, so put a site down to
+// build a new one right away, where the last one stood if we know where that was; while the
+// room does have a spawn its position is kept in `Memory.rooms[name].spawn`, and while it can't
+// place one, the tick to try again at and how long to wait after that are kept there as
+// `retryAt` and `retryTicks`
+pub fn rebuild_spawn(room: &Room) {
+    match room.controller() {
+        Some(controller) if controller.my() => {}
+        _ => return,
+    }
+    let room_memory = memory::room(room.name());
+    if let Some(spawn) = room.find(find::MY_SPAWNS, None).first() {
+        let spawn_memory = memory::child(&room_memory, "spawn");
+        memory::set_u32(&spawn_memory, "x", spawn.pos().x().u8().into());
+        memory::set_u32(&spawn_memory, "y", spawn.pos().y().u8().into());
+        memory::set_u32(&spawn_memory, "retryTicks", SPAWN_RETRY_TICKS);
+        return;
+    }
+    if room.find(find::MY_CONSTRUCTION_SITES, None).iter().any(|site| site.structure_type() == StructureType::Spawn) {
+        return;
+    }
+    let spawn_memory = memory::child(&room_memory, "spawn");
+    if memory::get_u32(&spawn_memory, "retryAt").is_some_and(|at| game::time() < at) {
+        return;
+    }
+
+    error!("{} has no spawn left! placing a site to rebuild one", room.name());
+    let last = memory::get_u32(&spawn_memory, "x")
+        .zip(memory::get_u32(&spawn_memory, "y"))
+        .and_then(|(x, y)| Some(Position::new(RoomCoordinate::new(x as u8).ok()?, RoomCoordinate::new(y as u8).ok()?, room.name())));
+    for pos in last.into_iter().chain(stamp::free_slots(room, StructureType::Spawn)).chain(building_spots(room)) {
+        match place_site(room, pos, StructureType::Spawn) {
+            Ok(()) => {
+                warn!("{}: placed a spawn site at {:?}", room.name(), pos);
+                memory::set_u32(&spawn_memory, "retryAt", 0);
+                memory::set_u32(&spawn_memory, "retryTicks", SPAWN_RETRY_TICKS);
+                return;
+            }
+            Err(ErrorCode::Full) => {
+                warn!("{}: no construction site budget left for a new spawn", room.name());
+                break;
+            }
+            Err(e) => debug!("couldn't place a spawn site at {:?}: {:?}", pos, e),
+        }
+    }
+    let wait = memory::get_u32(&spawn_memory, "retryTicks").unwrap_or(SPAWN_RETRY_TICKS);
+    warn!("{}: couldn't place a spawn site, trying again in {} ticks", room.name(), wait);
+    memory::set_u32(&spawn_memory, "retryAt", game::time() + wait);
+    memory::set_u32(&spawn_memory, "retryTicks", (wait * 2).min(SPAWN_RETRY_MAX_TICKS));
+}
+
+// below this controller level source containers get built before any extensions, since
+// static mining is what pays for everything else
+const CONTAINERS_FIRST_BELOW_LEVEL: u32 = 4;
+
+// whether every source in the room has a finished container next to it
+fn sources_have_containers(room: &Room) -> bool {
+    room.find(find::SOURCES, None).iter().all(|source| {
+        source.pos().find_in_range(find::STRUCTURES, 1).iter().any(|s| s.as_structure().structure_type() == StructureType::Container)
+    })
+}
+
+// the stamp's roads only get built from this controller level, once there's a bit of a base to
+// walk around
+const STAMP_ROADS_FROM_LEVEL: u32 = 3;
+
+// place construction sites for anything the current level allows that isn't built or planned
+// yet, on the room's stamp (see `stamp`) and, for the towers and extensions in rooms the stamp
+// doesn't fit or once it's full, wherever there's space; spawns, the storage and the terminal
+// only ever go on the stamp. early on, extensions wait until the source containers are built
+// (see `CONTAINERS_FIRST_BELOW_LEVEL`)
+// the whole planning pass again for an owned room, outside of a level change; what's already
+// built or planned is left alone, so it only ever fills in what's missing
+pub fn replan(room: &Room) {
+    if let Some(controller) = room.controller().filter(|controller| controller.my()) {
+        plan_room(room, controller.level() as u32);
+    }
+}
+
+pub fn plan_room(room: &Room, level: u32) {
+    let containers_first = level < CONTAINERS_FIRST_BELOW_LEVEL && !sources_have_containers(room);
+    if containers_first {
+        info!("{}: build order is source containers, then towers, then extensions", room.name());
+        plan_containers(room);
+        let room_memory = memory::room(room.name());
+        memory::set_u32(&room_memory, "extensionsDeferred", 1);
+    }
+    for structure_type in [StructureType::Tower, StructureType::Spawn, StructureType::Storage, StructureType::Terminal, StructureType::Extension] {
+        if containers_first && structure_type == StructureType::Extension {
+            continue;
+        }
+        let allowed = structure_type.controller_structures(level);
+        let existing = room.find(find::MY_STRUCTURES, None)
+            .iter()
+            .filter(|s| s.as_structure().structure_type() == structure_type)
+            .count() as u32;
+        let planned = room.find(find::MY_CONSTRUCTION_SITES, None)
+            .iter()
+            .filter(|site| site.structure_type() == structure_type)
+            .count() as u32;
+
+        let mut missing = allowed.saturating_sub(existing + planned);
+        if missing == 0 {
+            continue;
+        }
+        info!("{}: planning {} more {:?}", room.name(), missing, structure_type);
+        let anywhere = matches!(structure_type, StructureType::Tower | StructureType::Extension);
+        let spots = stamp::free_slots(room, structure_type).into_iter()
+            .chain(if anywhere { building_spots(room) } else { Vec::new() });
+        for pos in spots {
+            if missing == 0 {
+                break;
+            }
+            match place_site(room, pos, structure_type) {
+                Ok(()) => missing -= 1,
+                Err(ErrorCode::Full) => {
+                    debug!("out of construction site budget for {:?}", structure_type);
+                    break;
+                }
+                Err(e) => {
+                    warn!("couldn't place {:?} site at {:?}: {:?}", structure_type, pos, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    if level >= STAMP_ROADS_FROM_LEVEL {
+        for pos in stamp::free_slots(room, StructureType::Road) {
+            match place_site(room, pos, StructureType::Road) {
+                Ok(()) => {}
+                Err(ErrorCode::Full) => {
+                    debug!("out of construction site budget for the base's roads");
+                    break;
+                }
+                Err(e) => debug!("couldn't place road site at {:?}: {:?}", pos, e),
+            }
+        }
+    }
+}
+
+// a room with hostiles in it and no tower, built or planned, gets a tower site straight away
+// if its level allows one, rather than waiting for the next re-plan; it's the one structure
+// that can fight back on its own
+pub fn plan_emergency_tower(room: &Room) {
+    let level = match room.controller() {
+        Some(controller) if controller.my() => controller.level() as u32,
+        _ => return,
+    };
+    if StructureType::Tower.controller_structures(level) == 0 || room.find(find::HOSTILE_CREEPS, None).is_empty() {
+        return;
+    }
+    let has_tower = room.find(find::MY_STRUCTURES, None).iter().any(|s| s.as_structure().structure_type() == StructureType::Tower)
+        || room.find(find::MY_CONSTRUCTION_SITES, None).iter().any(|site| site.structure_type() == StructureType::Tower);
+    if has_tower {
+        return;
+    }
+    let Some(pos) = stamp::free_slots(room, StructureType::Tower).into_iter().chain(building_spots(room)).next() else {
+        warn!("{}: under attack with no tower and nowhere to put one!", room.name());
+        return;
+    };
+    match place_site(room, pos, StructureType::Tower) {
+        Ok(()) => warn!("{}: under attack with no tower, placing one at {:?}", room.name(), pos),
+        Err(e) => warn!("couldn't place emergency tower site at {:?}: {:?}", pos, e),
+    }
+}
+
+// free tiles around the first spawn (or the controller, in a room without one) in a
+// checkerboard pattern, nearest first, so that anything placed on them leaves walkable gaps
+// between structures; tiles the stamp has plans for are left to it
+fn building_spots(room: &Room) -> Vec<Position> {
+    let stamped = stamp::tiles(room).into_iter().map(|(pos, _)| pos).collect::<Vec<_>>();
+    let anchor = match room.find(find::MY_SPAWNS, None).first() {
+        Some(spawn) => spawn.pos(),
+        None => match room.controller() {
+            Some(controller) => controller.pos(),
+            None => return Vec::new(),
+        },
+    };
+    let terrain = room.get_terrain();
+    let keep_clear = room.find(find::SOURCES, None)
+        .iter()
+        .map(|source| source.pos())
+        .chain(room.controller().map(|controller| controller.pos()))
+        .collect::<Vec<_>>();
+
+    let mut spots = Vec::new();
+    for dx in -PLAN_RADIUS..=PLAN_RADIUS {
+        for dy in -PLAN_RADIUS..=PLAN_RADIUS {
+            if (dx + dy) % 2 != 0 || (dx.abs() < 2 && dy.abs() < 2) {
+                continue;
+            }
+            let pos = match anchor.checked_add((dx, dy)) {
+                Ok(pos) => pos,
+                Err(_) => continue,
+            };
+            // stay off the room edges, where exits are
+            if !(2..=47).contains(&pos.x().u8()) || !(2..=47).contains(&pos.y().u8()) {
+                continue;
+            }
+            if terrain.get(pos.x().u8(), pos.y().u8()) == Terrain::Wall {
+                continue;
+            }
+            if keep_clear.iter().any(|p| p.get_range_to(pos) <= 2) || stamped.contains(&pos) {
+                continue;
+            }
+            let occupied = pos.look_for(look::STRUCTURES).map_or(true, |s| !s.is_empty())
+                || pos.look_for(look::CONSTRUCTION_SITES).map_or(true, |s| !s.is_empty());
+            if !occupied {
+                spots.push(pos);
+            }
+        }
+    }
+    spots.sort_by_key(|pos| anchor.get_range_to(*pos));
+    spots
+}
+
+// put a site down to replace a destroyed source container, where it stood if that's known and
+// still free, and otherwise wherever `plan_containers` picks
+pub fn rebuild_container(room: &Room, pos: Option<Position>) {
+    match room.controller() {
+        Some(controller) if controller.my() => {}
+        _ => return,
+    }
+    if let Some(pos) = pos {
+        match place_site(room, pos, StructureType::Container) {
+            Ok(()) => {
+                info!("{}: placed site to rebuild container at {:?}", room.name(), pos);
+                return;
+            }
+            Err(e) => debug!("couldn't rebuild container at {:?}: {:?}", pos, e),
+        }
+    }
+    plan_containers(room);
+}
+
+// make sure every source (and the controller) has a container next to it, placing a site on
+// the best free tile if there's neither a container nor a site for one already; this is what
+// lets static miners and upgraders get going without any manual placement
+pub fn plan_containers(room: &Room) {
+    match room.controller() {
+        Some(controller) if controller.my() => {}
+        _ => return,
+    }
+    let level = room.controller().map_or(0, |controller| controller.level() as u32);
+    let mut budget = StructureType::Container.controller_structures(level).saturating_sub(
+        room.find(find::STRUCTURES, None).iter().filter(|s| s.as_structure().structure_type() == StructureType::Container).count() as u32
+            + room.find(find::MY_CONSTRUCTION_SITES, None).iter().filter(|site| site.structure_type() == StructureType::Container).count() as u32,
+    );
+
+    let anchor = room.find(find::MY_SPAWNS, None).first().map(|spawn| spawn.pos());
+    let sources = room.find(find::SOURCES, None).iter().map(|source| source.pos()).collect::<Vec<_>>();
+    let controller = room.controller().map(|controller| controller.pos());
+    let targets = sources.iter()
+        .map(|source| (*source, 1))
+        .chain(controller.map(|controller| (controller, 2)))
+        .collect::<Vec<_>>();
+    let terrain = room.get_terrain();
+
+    for (target, range) in targets {
+        if budget == 0 {
+            return;
+        }
+        // a source's container near the controller doesn't count as the controller's own
+        let for_controller = Some(target) == controller;
+        let serves_target = |pos: Position| !for_controller || !sources.iter().any(|source| source.is_near_to(pos));
+        let existing = target.find_in_range(find::STRUCTURES, range).iter()
+            .filter(|s| s.as_structure().structure_type() == StructureType::Container)
+            .map(|s| s.pos())
+            .chain(target.find_in_range(find::MY_CONSTRUCTION_SITES, range).iter().filter(|site| site.structure_type() == StructureType::Container).map(|site| site.pos()))
+            .find(|pos| serves_target(*pos));
+        if let Some(pos) = existing {
+            if for_controller && upgrading::controller_container_pos(room.name()) != Some(pos) {
+                upgrading::note_controller_container(pos);
+            }
+            continue;
+        }
+
+        let mut candidates = Vec::new();
+        let reach = range as i32;
+        for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                let pos = match target.checked_add((dx, dy)) {
+                    Ok(pos) if pos != target => pos,
+                    _ => continue,
+                };
+                let tile = terrain.get(pos.x().u8(), pos.y().u8());
+                if tile == Terrain::Wall {
+                    continue;
+                }
+                let occupied = pos.look_for(look::STRUCTURES).map_or(true, |s| !s.is_empty())
+                    || pos.look_for(look::CONSTRUCTION_SITES).map_or(true, |s| !s.is_empty());
+                if !occupied && serves_target(pos) {
+                    // swamps are slow to walk on and off, prefer plains, then the tile
+                    // closest to home
+                    let distance = anchor.map_or(0, |anchor| anchor.get_range_to(pos));
+                    candidates.push(((tile == Terrain::Swamp, distance), pos));
+                }
+            }
+        }
+
+        if let Some((_, pos)) = candidates.into_iter().min_by_key(|(score, _)| *score) {
+            match place_site(room, pos, StructureType::Container) {
+                Ok(()) => {
+                    info!("{}: placed container site at {:?}", room.name(), pos);
+                    if for_controller {
+                        upgrading::note_controller_container(pos);
+                    }
+                    budget -= 1;
+                }
+                Err(ErrorCode::Full) => return,
+                Err(e) => warn!("couldn't place container site at {:?}: {:?}", pos, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use log::*;
+use screeps::{
+    constants::{find, Part, CREEP_SPAWN_TIME},
+    enums::StructureObject,
+    game,
+    local::RoomName,
+    objects::{Creep, Room},
+    prelude::*,
+};
+use wasm_bindgen::JsCast;
+
+use crate::{harass, memory, mining, remote, role::{self, Role}, squad, strategy::SpawnStrategy};
+
+// how many creeps each room keeps: a few to get going, plus more for every source to mine,
+// the bigger extension counts of a higher controller level and the construction work waiting
+// to be done. `Memory.rooms[name].maxCreeps` overrides all of it, and
+// `Memory.rooms[name].caps[role]` caps any single role (see `Role::as_str`)
+
+const BASE_CREEPS: usize = 2;
+const CREEPS_PER_SOURCE: usize = 2;
+// one more per this many construction sites
+const SITES_PER_CREEP: usize = 5;
+const MAX_CREEPS: usize = 20;
+
+pub fn max_creeps(room: &Room) -> usize {
+    if let Some(max) = memory::get_u32(&memory::room(room.name()), "maxCreeps") {
+        return max as usize;
+    }
+    let sources = room.find(find::SOURCES, None).len();
+    let level = room.controller().map_or(0, |controller| controller.level() as usize);
+    let sites = room.find(find::MY_CONSTRUCTION_SITES, None).len();
+    (BASE_CREEPS + sources * CREEPS_PER_SOURCE + level.saturating_sub(2) + sites / SITES_PER_CREEP).min(MAX_CREEPS)
+}
+
+// the most creeps of a role the room should have; harvesters are limited by how many can
+// find somewhere to stand around the sources, fighters by how many are needed to see off a
+// typical raid, and the rest only by the room's overall cap
+pub fn role_cap(room: &Room, role: Role) -> usize {
+    let caps = memory::child(&memory::room(room.name()), "caps");
+    if let Some(cap) = memory::get_u32(&caps, role.as_str()) {
+        return cap as usize;
+    }
+    match role {
+        Role::Harvester => room.find(find::SOURCES, None)
+            .iter()
+            .map(|source| (mining::open_tiles(source) as usize).min(CREEPS_PER_SOURCE))
+            .sum(),
+        Role::Defender => 3,
+        Role::Medic => 2,
+        // only ever spawned for remotes
+        Role::Reserver => 0,
+        Role::Carrier | Role::Worker => max_creeps(room),
+    }
+}
+
+// the room a creep belongs to: the one it was spawned from, kept as `home` in its memory, or
+// for a creep from before that was written down, the room it's in
+pub fn home_of(creep: &Creep) -> RoomName {
+    remote::home_room(creep).unwrap_or_else(|| creep.pos().room_name())
+}
+
+// the creeps that belong to the room, wherever they are right now, bar the ones working its
+// remotes
+pub fn creep_count(room: &Room) -> usize {
+    game::creeps().values()
+        .filter(|creep| home_of(creep) == room.name() && remote::remote_room(creep).is_none())
+        .count()
+}
+
+pub fn role_count(room: &Room, role: Role) -> usize {
+    game::creeps().values()
+        .filter(|creep| home_of(creep) == room.name() && remote::remote_room(creep).is_none() && Role::of(creep) == role)
+        .count()
+}
+
+// whether a creep has less time left to live than it'd take to spawn a replacement with the
+// same body and walk it out from the nearest spawn, meaning the replacement should be getting
+// started now if there's not to be a gap; a creep in a room without our spawn isn't judged
+pub fn replacement_due(creep: &Creep) -> bool {
+    let Some(ticks_to_live) = creep.ticks_to_live() else {
+        return false;
+    };
+    let Some(spawn) = creep.pos().find_closest_by_range(find::MY_SPAWNS) else {
+        return false;
+    };
+    let spawn_time = creep.body().len() as u32 * CREEP_SPAWN_TIME;
+    let travel_time = creep.pos().get_range_to(spawn.pos());
+    ticks_to_live < spawn_time + travel_time
+}
+
+// a working creep with fewer than this percentage of its WORK and CARRY parts still working is
+// more use recycled and respawned than limping on
+const RECYCLE_BELOW_PERCENT: u32 = 50;
+
+// whether a harvester, carrier or worker has had enough of its body shot off that it should
+// be recycled for a fresh one; only once the fighting's over and only in rooms with no tower
+// to patch it back up, and never boosted creeps, which are worth keeping even half broken
+pub fn worth_recycling(creep: &Creep, room: &Room) -> bool {
+    if !matches!(Role::of(creep), Role::Harvester | Role::Carrier | Role::Worker) {
+        return false;
+    }
+    let body = creep.body();
+    let working = body.iter().filter(|part| matches!(part.part(), Part::Work | Part::Carry)).collect::<Vec<_>>();
+    if working.is_empty() || working.iter().any(|part| part.boost().is_some()) {
+        return false;
+    }
+    let intact = working.iter().filter(|part| part.hits() > 0).count() as u32;
+    if intact * 100 >= working.len() as u32 * RECYCLE_BELOW_PERCENT {
+        return false;
+    }
+    let towers = room.find(find::MY_STRUCTURES, None)
+        .iter()
+        .any(|s| matches!(s, StructureObject::StructureTower(_)));
+    room.find(find::HOSTILE_CREEPS, None).is_empty() && !towers
+}
+
+// creeps are checked for being surplus to what their room needs this often, in ticks
+pub const UNNEEDED_CHECK_TICKS: u32 = 100;
+
+// and one has to be surplus this many checks running before anything's done about it, so a
+// defender isn't thrown away the moment a raid ends
+const UNNEEDED_CHECKS: u32 = 5;
+
+// whether creeps nothing needs any more get recycled; the default is only to log them.
+// `Memory.recycleUnneeded = 1` turns it on
+pub fn recycle_unneeded() -> bool {
+    memory::get_u32(&screeps::memory::ROOT, "recycleUnneeded").is_some_and(|recycle| recycle > 0)
+}
+
+// how many creeps built for `role` the room has a use for right now: fighters only while
+// there's something to fight (bar the standing defenders some strategies keep), reservers
+// never at home, and the rest up to their caps
+fn wanted(room: &Room, role: Role) -> usize {
+    let hostiles = !room.find(find::HOSTILE_CREEPS, None).is_empty();
+    match role {
+        Role::Defender if hostiles => role_cap(room, role),
+        Role::Defender => SpawnStrategy::current().standing_defenders(),
+        Role::Medic if hostiles => role_cap(room, role),
+        Role::Medic | Role::Reserver => 0,
+        Role::Harvester | Role::Carrier | Role::Worker => role_cap(room, role),
+    }
+}
+
+// what a creep's body is built for. harvesters that fill a link or container carry a CARRY
+// part, which on its own reads as a worker's body, so one spawned as a harvester stays one
+pub fn built_for(creep: &Creep) -> Role {
+    match (Role::of(creep), role::infer_role(creep)) {
+        (Role::Harvester, Role::Worker) => Role::Harvester,
+        (_, inferred) => inferred,
+    }
+}
+
+// the creeps belonging to the room whose bodies nothing in the room needs, as judged by what
+// those bodies are built for rather than what they were spawned as; each creep's run of checks
+// as surplus is kept as `unneeded` in its memory. the ones with the least life left go first,
+// and a creep whose body doesn't match the role it was spawned for gets logged along the way
+pub fn track_unneeded(room: &Room) -> Vec<Creep> {
+    let creeps = game::creeps().values()
+        .filter(|creep| !creep.spawning() && home_of(creep) == room.name())
+        .filter(|creep| remote::remote_room(creep).is_none() && squad::squad_of(creep).is_none() && harass::raid_of(creep).is_none())
+        .collect::<Vec<_>>();
+    let mut unneeded = Vec::new();
+    for role in Role::ALL {
+        let mut built_for = creeps.iter().filter(|creep| built_for(creep) == role).collect::<Vec<_>>();
+        for creep in built_for.iter() {
+            if Role::of(creep) != role {
+                debug!("{}: spawned as a {} but built like a {}", creep.name(), Role::of(creep).as_str(), role.as_str());
+            }
+        }
+        let surplus = built_for.len().saturating_sub(wanted(room, role));
+        built_for.sort_by_key(|creep| creep.ticks_to_live().unwrap_or(0));
+        for (index, creep) in built_for.into_iter().enumerate() {
+            let memory = creep.memory();
+            if !memory.is_object() {
+                continue;
+            }
+            let memory = memory.unchecked_into();
+            let checks = if index < surplus { memory::get_u32(&memory, "unneeded").unwrap_or(0) + 1 } else { 0 };
+            memory::set_u32(&memory, "unneeded", checks);
+            if checks == UNNEEDED_CHECKS {
+                warn!("{}: nothing in {} has needed a {} for {} ticks", creep.name(), room.name(), role.as_str(), UNNEEDED_CHECKS * UNNEEDED_CHECK_TICKS);
+            }
+            if checks >= UNNEEDED_CHECKS {
+                unneeded.push(creep.clone());
+            }
+        }
+    }
+    unneeded
+}
@@ -0,0 +1,472 @@
+use std::collections::HashSet;
+
+use js_sys::{JsString, Object, Reflect};
+use log::*;
+use screeps::{
+    constants::{find, Part, ResourceType, StructureType, CREEP_CLAIM_LIFE_TIME, CREEP_SPAWN_TIME},
+    game,
+    local::{ObjectId, Position, RoomCoordinate, RoomName},
+    enums::StructureObject,
+    objects::{Creep, Room, Source, StructureContainer},
+    prelude::*,
+};
+use wasm_bindgen::JsCast;
+
+use crate::{expansion, harass, memory, movement, role::Role};
+
+// remote mining: harvesting the sources of unowned rooms next to an owned room and hauling the
+// energy back home. each remote room is tracked in `Memory.remotes[room]` as
+// `{ home, hostiles, threatUntil, reservedUntil, sources: { [id]: { x, y } } }`, and the
+// creeps working it carry `home` and `remote` in their own memory
+
+// at most this many remote rooms are worked from each home room
+const MAX_REMOTES_PER_HOME: usize = 2;
+
+// remote miners are built from this many WORK parts at most, enough to empty a source in an
+// unreserved room before it regenerates
+const REMOTE_WORK_PARTS: usize = 3;
+
+// and remote carriers from this many CARRY parts at most, it's a long walk home
+const REMOTE_CARRY_PARTS: usize = 10;
+
+// reservers are built from this many CLAIM parts at most; each one adds a tick to the
+// reservation every tick, so two is enough to build it up between replacements
+const RESERVER_CLAIM_PARTS: usize = 2;
+
+// a reservation with less than this left on it gets a reserver sent to top it up; it can hold
+// at most 5000
+const RESERVATION_TOP_UP_TICKS: u32 = 3000;
+
+// roughly how long a creep takes to cross a room, for working out when a reserver's
+// replacement has to set off
+const ROOM_CROSSING_TICKS: u32 = 50;
+
+// a remote room stays off limits for this many ticks after hostiles were last seen in it, so
+// creeps don't go straight back in the moment they've pulled out and lost sight of it
+const THREAT_COOLDOWN_TICKS: u32 = 300;
+
+fn remotes() -> Object {
+    memory::child(&screeps::memory::ROOT, "remotes")
+}
+
+fn remote_memory(remote: RoomName) -> Object {
+    memory::child(&remotes(), &remote.to_string())
+}
+
+fn creep_memory(creep: &Creep) -> Option<Object> {
+    let memory = creep.memory();
+    memory.is_object().then(|| memory.unchecked_into())
+}
+
+fn center(room: RoomName) -> Position {
+    Position::new(RoomCoordinate::new(25).unwrap(), RoomCoordinate::new(25).unwrap(), room)
+}
+
+// the remote room this creep was spawned to work, if it's a remote creep at all
+pub fn remote_room(creep: &Creep) -> Option<RoomName> {
+    memory::get_string(&creep_memory(creep)?, "remote")?.parse().ok()
+}
+
+pub fn home_room(creep: &Creep) -> Option<RoomName> {
+    memory::get_string(&creep_memory(creep)?, "home")?.parse().ok()
+}
+
+// add `remote` and `home` to the memory of a creep about to be spawned for a remote room
+pub fn tag_memory(memory: &Object, home: RoomName, remote: RoomName) {
+    memory::set_string(memory, "home", &home.to_string());
+    memory::set_string(memory, "remote", &remote.to_string());
+}
+
+// look over the rooms next to an owned room for ones worth mining: anything nobody else owns
+// or has reserved, and that isn't a source keeper room. rooms we can't see yet are noted down
+// without sources, and filled in once a remote creep gets there and gives us vision; rooms
+// scouted with an observer (see `observe`) get first pick
+pub fn survey(home: &Room) {
+    let username = match home.controller() {
+        Some(controller) if controller.my() => controller.owner().map(|owner| owner.username()),
+        _ => return,
+    };
+    // rooms found by the observer come first, they've been picked out as the best around
+    if remotes_of(home.name()).len() < MAX_REMOTES_PER_HOME {
+        adopt_scouted(home);
+    }
+    let remotes = remotes();
+    for remote in game::map::describe_exits(home.name()).values() {
+        if movement::is_source_keeper_room(remote) {
+            continue;
+        }
+        let key = remote.to_string();
+        let tracked = memory::keys(&remotes).contains(&key);
+        let Some(room) = game::rooms().get(remote) else {
+            if !tracked && remotes_of(home.name()).len() < MAX_REMOTES_PER_HOME {
+                info!("noting {} down as a possible remote for {}", remote, home.name());
+                let remote_memory = remote_memory(remote);
+                memory::set_string(&remote_memory, "home", &home.name().to_string());
+            }
+            continue;
+        };
+        let taken = room.controller().is_some_and(|controller| {
+            controller.owner().is_some()
+                || controller.reservation().is_some_and(|reservation| Some(reservation.username()) != username)
+        });
+        if taken {
+            if tracked {
+                warn!("{} has been taken by someone else, no longer mining it", remote);
+                let _ = js_sys::Reflect::delete_property(&remotes, &key.into());
+            }
+            continue;
+        }
+        if !tracked && remotes_of(home.name()).len() >= MAX_REMOTES_PER_HOME {
+            continue;
+        }
+        let remote_memory = remote_memory(remote);
+        if memory::get_string(&remote_memory, "home").is_some_and(|other| other != home.name()) {
+            // already worked from another of our rooms
+            continue;
+        }
+        memory::set_string(&remote_memory, "home", &home.name().to_string());
+        let sources = memory::child(&remote_memory, "sources");
+        for source in room.find(find::SOURCES, None) {
+            let source_memory = memory::child(&sources, &source.id().to_string());
+            memory::set_u32(&source_memory, "x", source.pos().x().u8().into());
+            memory::set_u32(&source_memory, "y", source.pos().y().u8().into());
+        }
+    }
+}
+
+// home rooms with an observer look further afield for remotes, up to this many rooms away,
+// scoring each room they look at and taking on the best of them; what's been seen is kept in
+// `Memory.scouted[room]` as `{ home, distance, score, layout, claimScore, at, sources: { [id]: { x, y } } }`
+const SCOUT_RANGE: u32 = 2;
+
+// scouted rooms get looked at again after this many ticks, in case they've been claimed
+const RESCOUT_TICKS: u32 = 5000;
+
+// a source is worth this much to a remote's score, and every room of distance from home
+// takes this much off it again
+const SOURCE_SCORE: u32 = 10;
+const DISTANCE_COST: u32 = 4;
+
+fn scouted() -> Object {
+    memory::child(&screeps::memory::ROOT, "scouted")
+}
+
+// the rooms within `SCOUT_RANGE` of `home` that could be remotes, with how many rooms away
+// each one is
+fn scout_candidates(home: RoomName) -> Vec<(RoomName, u32)> {
+    let mut seen = HashSet::from([home]);
+    let mut frontier = vec![home];
+    let mut candidates = Vec::new();
+    for distance in 1..=SCOUT_RANGE {
+        let mut next = Vec::new();
+        for room in frontier {
+            for neighbour in game::map::describe_exits(room).values() {
+                if seen.insert(neighbour) && !movement::is_source_keeper_room(neighbour) {
+                    candidates.push((neighbour, distance));
+                    next.push(neighbour);
+                }
+            }
+        }
+        frontier = next;
+    }
+    candidates
+}
+
+// note down what an observed room has to offer; a room that's been claimed or reserved by
+// someone else scores nothing, and stops being mined if it was one of our remotes
+fn record_scouted(home: &Room, room: &Room, distance: u32) {
+    let username = home.controller().and_then(|controller| controller.owner()).map(|owner| owner.username());
+    let taken = room.controller().is_some_and(|controller| {
+        controller.owner().is_some()
+            || controller.reservation().is_some_and(|reservation| Some(reservation.username()) != username)
+    });
+    let room_memory = memory::child(&scouted(), &room.name().to_string());
+    let sources = Object::new();
+    for source in room.find(find::SOURCES, None) {
+        let source_memory = memory::child(&sources, &source.id().to_string());
+        memory::set_u32(&source_memory, "x", source.pos().x().u8().into());
+        memory::set_u32(&source_memory, "y", source.pos().y().u8().into());
+    }
+    let score = if taken { 0 } else { (memory::keys(&sources).len() as u32 * SOURCE_SCORE).saturating_sub(distance * DISTANCE_COST) };
+    // rooms nobody's taken are also weighed up as somewhere to expand to, by how well a base
+    // would fit; one scoring nothing there isn't worth claiming however many sources it has
+    let layout = if taken {
+        0
+    } else {
+        let source_positions = room.find(find::SOURCES, None).iter().map(|source| source.pos()).collect::<Vec<_>>();
+        expansion::layout_score(room.name(), &source_positions, room.controller().map(|controller| controller.pos()))
+    };
+    let claim_score = if layout == 0 { 0 } else { score + layout };
+    memory::set_u32(&room_memory, "layout", layout);
+    memory::set_u32(&room_memory, "claimScore", claim_score);
+    let _ = Reflect::set(&room_memory, &JsString::from("sources"), &sources);
+    memory::set_string(&room_memory, "home", &home.name().to_string());
+    memory::set_u32(&room_memory, "distance", distance);
+    memory::set_u32(&room_memory, "score", score);
+    memory::set_u32(&room_memory, "at", game::time());
+    info!("{}: scouted {}, scoring it {} as a remote and {} to claim", home.name(), room.name(), score, claim_score);
+    if claim_score > 0 && expansion::best_claim(&scouted()).is_some_and(|(best, _)| best == room.name()) {
+        info!("{} is now the best room we've seen to expand into", room.name());
+    }
+
+    if taken {
+        harass::record_room(room);
+    }
+
+    let key = room.name().to_string();
+    if taken && memory::keys(&remotes()).contains(&key) {
+        warn!("{} has been taken by someone else, no longer mining it", room.name());
+        let _ = Reflect::delete_property(&remotes(), &key.into());
+    }
+}
+
+// point the home room's observer at the next room due a look, recording whatever it looked at
+// last tick (which is visible now); `Memory.rooms[home].observing` is the room it's looking at
+pub fn observe(home: &Room) {
+    let observer = home.find(find::MY_STRUCTURES, None).into_iter().find_map(|s| match s {
+        StructureObject::StructureObserver(observer) => Some(observer),
+        _ => None,
+    });
+    let Some(observer) = observer else {
+        return;
+    };
+    let home_memory = memory::room(home.name());
+    let candidates = scout_candidates(home.name());
+    if let Some(observed) = memory::get_string(&home_memory, "observing").and_then(|name| name.parse::<RoomName>().ok()) {
+        let distance = candidates.iter().find(|(room, _)| *room == observed).map_or(SCOUT_RANGE, |(_, distance)| *distance);
+        if let Some(room) = game::rooms().get(observed) {
+            record_scouted(home, &room, distance);
+        }
+        let _ = Reflect::delete_property(&home_memory, &JsString::from("observing"));
+    }
+
+    let scouted = scouted();
+    let due = candidates.into_iter()
+        .map(|(room, _)| (room, memory::get_u32(&memory::child(&scouted, &room.to_string()), "at")))
+        .filter(|(_, at)| at.is_none_or(|at| game::time() >= at + RESCOUT_TICKS))
+        .min_by_key(|(_, at)| at.unwrap_or(0));
+    if let Some((room, _)) = due {
+        match observer.observe_room(room) {
+            Ok(()) => memory::set_string(&home_memory, "observing", &room.to_string()),
+            Err(e) => warn!("couldn't observe {}: {:?}", room, e),
+        }
+    }
+}
+
+// take on the best-scoring room `home` has scouted that nobody's mining yet, copying over what
+// we know of its sources so the remote creeps know where to go before they can see it
+fn adopt_scouted(home: &Room) {
+    let scouted = scouted();
+    let remotes = remotes();
+    let tracked = memory::keys(&remotes);
+    let best = memory::keys(&scouted)
+        .into_iter()
+        .filter(|key| !tracked.contains(key))
+        .map(|key| {
+            let room_memory = memory::child(&scouted, &key);
+            (key, room_memory)
+        })
+        .filter(|(_, room_memory)| memory::get_string(room_memory, "home") == Some(home.name().to_string()))
+        .map(|(key, room_memory)| (memory::get_u32(&room_memory, "score").unwrap_or(0), key, room_memory))
+        .filter(|(score, _, _)| *score > 0)
+        .max_by_key(|(score, _, _)| *score);
+    if let Some((score, key, room_memory)) = best {
+        info!("taking on scouted room {} as a remote for {}, it scored {}", key, home.name(), score);
+        let remote_memory = memory::child(&remotes, &key);
+        memory::set_string(&remote_memory, "home", &home.name().to_string());
+        let _ = Reflect::set(&remote_memory, &JsString::from("sources"), &memory::child(&room_memory, "sources"));
+    }
+}
+
+// the remote rooms worked from `home`
+pub fn remotes_of(home: RoomName) -> Vec<RoomName> {
+    let remotes = remotes();
+    memory::keys(&remotes)
+        .into_iter()
+        .filter(|key| memory::get_string(&memory::child(&remotes, key), "home") == Some(home.to_string()))
+        .filter_map(|key| key.parse().ok())
+        .collect()
+}
+
+// a remote carrier with room to spare after emptying its own remote's containers tops up
+// from the containers of any other remote of the same home next to the room it's in, as long
+// as they hold at least this much, sooner than going home part full. once it's holding some
+// energy the same goes for its own remote's containers, or it'd keep going back for the
+// trickle its miners have put in since
+pub const ROUTE_MIN_ENERGY: u32 = 200;
+
+// the next container a remote carrier should call at on its way round the home's remotes, if
+// any: the fullest one in a neighbouring remote that's clear of hostiles and that no other
+// carrier is already on its way to (`taken`)
+pub fn next_on_route(creep: &Creep, taken: &[ObjectId<StructureContainer>]) -> Option<StructureContainer> {
+    let own = remote_room(creep)?;
+    let home = home_room(creep)?;
+    let here = creep.pos().room_name();
+    remotes_of(home)
+        .into_iter()
+        .filter(|remote| *remote != own && !threatened(*remote))
+        .filter(|remote| *remote == here || game::map::get_room_linear_distance(here, *remote, false) <= 1)
+        .filter_map(|remote| game::rooms().get(remote))
+        .flat_map(|room| room.find(find::STRUCTURES, None))
+        .filter_map(|s| match s {
+            StructureObject::StructureContainer(container) => Some(container),
+            _ => None,
+        })
+        .filter(|container| container.store().get_used_capacity(Some(ResourceType::Energy)) >= ROUTE_MIN_ENERGY && !taken.contains(&container.id()))
+        .max_by_key(|container| container.store().get_used_capacity(Some(ResourceType::Energy)))
+}
+
+// the sources we know about in a remote room
+pub fn sources_of(remote: RoomName) -> Vec<ObjectId<Source>> {
+    let sources = memory::child(&remote_memory(remote), "sources");
+    memory::keys(&sources).into_iter().filter_map(|id| id.parse().ok()).collect()
+}
+
+// where a remote source is, from memory, for when we can't see its room to resolve it
+pub fn source_position(id: ObjectId<Source>) -> Option<Position> {
+    let remotes = remotes();
+    memory::keys(&remotes).into_iter().find_map(|key| {
+        let sources = memory::child(&memory::child(&remotes, &key), "sources");
+        if !memory::keys(&sources).contains(&id.to_string()) {
+            return None;
+        }
+        let source = memory::child(&sources, &id.to_string());
+        let x = RoomCoordinate::new(memory::get_u32(&source, "x")? as u8).ok()?;
+        let y = RoomCoordinate::new(memory::get_u32(&source, "y")? as u8).ok()?;
+        Some(Position::new(x, y, key.parse().ok()?))
+    })
+}
+
+// note whether each remote room has hostiles in it, and keep it marked as threatened until
+// `THREAT_COOLDOWN_TICKS` after they were last seen; once our creeps have pulled out we can't
+// see the room any more, so after the cooldown it's taken to be clear again and the next
+// creep in takes a look
+pub fn track_threats() {
+    let remotes = remotes();
+    for key in memory::keys(&remotes) {
+        let room = key.parse().ok().and_then(|name: RoomName| game::rooms().get(name));
+        let remote_memory = memory::child(&remotes, &key);
+        let hostiles = room.is_some_and(|room| !room.find(find::HOSTILE_CREEPS, None).is_empty());
+        if hostiles {
+            memory::set_u32(&remote_memory, "threatUntil", game::time() + THREAT_COOLDOWN_TICKS);
+        }
+        let threatened = hostiles || memory::get_u32(&remote_memory, "threatUntil").is_some_and(|until| game::time() < until);
+        let was = memory::get_u32(&remote_memory, "hostiles").unwrap_or(0) > 0;
+        if threatened && !was {
+            warn!("hostiles in remote room {}, pulling its creeps back and holding off spawning for it", key);
+        } else if !threatened && was {
+            info!("remote room {} has been clear for {} ticks, going back in", key, THREAT_COOLDOWN_TICKS);
+        }
+        memory::set_u32(&remote_memory, "hostiles", threatened as u32);
+    }
+}
+
+// note when each remote room we can see has its reservation (ours, since anyone else's has the
+// room dropped as taken) running out, as `Memory.remotes[room].reservedUntil`; rooms we can't
+// see keep the last time we saw
+pub fn track_reservations() {
+    let remotes = remotes();
+    for key in memory::keys(&remotes) {
+        let Some(room) = key.parse().ok().and_then(|name: RoomName| game::rooms().get(name)) else {
+            continue;
+        };
+        let ticks_to_end = room.controller().and_then(|controller| controller.reservation()).map_or(0, |reservation| reservation.ticks_to_end());
+        memory::set_u32(&memory::child(&remotes, &key), "reservedUntil", game::time() + ticks_to_end);
+    }
+}
+
+// whether a remote room's reservation needs topping up; reservers only live for
+// `CREEP_CLAIM_LIFE_TIME`, a sixth of everything else, so one already on the job only counts
+// while it'll outlast the time it takes to spawn and walk out its replacement
+fn needs_reserver(home: &Room, remote: RoomName, creeps: &[Creep]) -> bool {
+    let reserved_for = memory::get_u32(&remote_memory(remote), "reservedUntil").map_or(0, |until| until.saturating_sub(game::time()));
+    if reserved_for >= RESERVATION_TOP_UP_TICKS {
+        return false;
+    }
+    let spawn_time = (RESERVER_CLAIM_PARTS * 2) as u32 * CREEP_SPAWN_TIME;
+    let travel_time = game::map::get_room_linear_distance(home.name(), remote, false) * ROOM_CROSSING_TICKS;
+    let lead_time = (spawn_time + travel_time).min(CREEP_CLAIM_LIFE_TIME);
+    !creeps.iter().any(|creep| {
+        remote_room(creep) == Some(remote) && Role::of(creep) == Role::Reserver && creep.ticks_to_live().is_none_or(|ttl| ttl > lead_time)
+    })
+}
+
+pub fn threatened(remote: RoomName) -> bool {
+    memory::get_u32(&remote_memory(remote), "hostiles").unwrap_or(0) > 0
+}
+
+// remote creeps whose room has hostiles in it head back home and wait there until it's clear
+pub fn retreating(creep: &Creep) -> bool {
+    remote_room(creep).is_some_and(threatened)
+}
+
+pub fn retreat(creep: &Creep) {
+    if let Some(home) = home_room(creep) {
+        if creep.pos().room_name() != home {
+            info!("{}: retreating home to {}", creep.name(), home);
+            let _ = movement::travel_to(creep, center(home));
+        }
+    }
+}
+
+// the next remote creep `home` should spawn, if any: a miner for each known source (or one to
+// go and take a look if we don't know the sources yet), then a carrier for each miner, then a
+// reserver once the home room can afford one
+pub fn needed_creep(home: &Room) -> Option<(RoomName, Role)> {
+    let creeps = game::creeps().values().collect::<Vec<_>>();
+    for remote in remotes_of(home.name()) {
+        if threatened(remote) {
+            continue;
+        }
+        let working = |role: Role| creeps.iter().filter(|creep| remote_room(creep) == Some(remote) && Role::of(creep) == role).count();
+        let sources = sources_of(remote).len();
+        if working(Role::Harvester) < sources.max(1) {
+            return Some((remote, Role::Harvester));
+        }
+        if working(Role::Carrier) < sources {
+            return Some((remote, Role::Carrier));
+        }
+        let reserver_cost = Part::Claim.cost() + Part::Move.cost();
+        if sources > 0 && home.energy_capacity_available() >= reserver_cost && needs_reserver(home, remote, &creeps) {
+            return Some((remote, Role::Reserver));
+        }
+    }
+    None
+}
+
+pub fn body(role: Role, energy: u32) -> Vec<Part> {
+    let (unit, max_units) = match role {
+        Role::Harvester => ([Part::Work, Part::Move], REMOTE_WORK_PARTS),
+        Role::Reserver => ([Part::Claim, Part::Move], RESERVER_CLAIM_PARTS),
+        _ => ([Part::Carry, Part::Move], REMOTE_CARRY_PARTS),
+    };
+    let unit_cost: u32 = unit.iter().map(|part| part.cost()).sum();
+    let units = ((energy / unit_cost) as usize).min(max_units);
+    let mut body = Vec::new();
+    for _ in 0..units {
+        body.extend_from_slice(&unit);
+    }
+    body
+}
+
+// a remote miner's source: one in its remote room nobody else is on yet. None with the room
+// still unknown means it should just head over there so we can see what's in it
+pub fn free_source(remote: RoomName, taken: &[ObjectId<Source>]) -> Option<ObjectId<Source>> {
+    sources_of(remote).into_iter().find(|source| !taken.contains(source))
+}
+
+// a construction site in one of a home room's remotes, for builders with nothing left to do at
+// home; this goes by `game::construction_sites()`, so sites in remotes we can't currently see
+// count as well, and builders just head over to where they are. closest to finished first,
+// and remotes with hostiles in them are left alone
+pub fn construction_site(home: RoomName) -> Option<(Position, StructureType)> {
+    let remotes = remotes_of(home);
+    game::construction_sites().values()
+        .filter(|site| remotes.contains(&site.pos().room_name()) && !threatened(site.pos().room_name()))
+        .min_by_key(|site| site.progress_total() - site.progress())
+        .map(|site| (site.pos(), site.structure_type()))
+}
+
+pub fn head_to(creep: &Creep, remote: RoomName) {
+    let _ = movement::travel_to(creep, center(remote));
+}
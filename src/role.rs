@@ -0,0 +1,148 @@
+use js_sys::{JsString, Object, Reflect};
+use screeps::{constants::Part, objects::Creep, prelude::*};
+
+// what a creep is for, which decides what kinds of task it can be given; this is stored in
+// `Memory.creeps[name].role` when it's spawned, and also encoded as a prefix of the creep's
+// name so it can always be recovered even if everything else about the creep is lost
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    // static miner, WORK and no CARRY
+    Harvester,
+    // pure hauler, CARRY and no WORK
+    Carrier,
+    // both WORK and CARRY - hauls, builds, repairs and upgrades
+    Worker,
+    Defender,
+    Medic,
+    // CLAIM and MOVE, keeps a remote room's controller reserved
+    Reserver,
+}
+
+impl Role {
+    pub const ALL: [Role; 6] = [Role::Harvester, Role::Carrier, Role::Worker, Role::Defender, Role::Medic, Role::Reserver];
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Role::Harvester => "harv-",
+            Role::Carrier => "carr-",
+            Role::Worker => "work-",
+            Role::Defender => "def-",
+            Role::Medic => "med-",
+            Role::Reserver => "res-",
+        }
+    }
+
+    // how the role is written in creep memory
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Harvester => "harvester",
+            Role::Carrier => "carrier",
+            Role::Worker => "worker",
+            Role::Defender => "defender",
+            Role::Medic => "medic",
+            Role::Reserver => "reserver",
+        }
+    }
+
+    // the initial memory for a creep spawned into this role
+    pub fn memory(self) -> Object {
+        let memory = Object::new();
+        let _ = Reflect::set(&memory, &JsString::from("role"), &JsString::from(self.as_str()));
+        memory
+    }
+
+    // the role recorded in the creep's memory, if it has one
+    pub fn from_memory(creep: &Creep) -> Option<Role> {
+        let role = Reflect::get(&creep.memory(), &JsString::from("role")).ok()?.as_string()?;
+        Role::ALL.iter().copied().find(|r| r.as_str() == role)
+    }
+
+    // recover the role from a creep name like `harv-1234-0`; creeps spawned before roles were
+    // part of the name don't have one
+    pub fn from_name(name: &str) -> Option<Role> {
+        Role::ALL.iter().copied().find(|role| name.starts_with(role.prefix()))
+    }
+
+    // the role from the creep's memory, then its name, and failing both, from its body
+    pub fn of(creep: &Creep) -> Role {
+        Role::from_memory(creep)
+            .or_else(|| Role::from_name(&creep.name()))
+            .unwrap_or_else(|| infer_role(creep))
+    }
+
+    // the parts that matter most to the role, most important first, for cutting a body down
+    // to size; anything not listed goes first
+    pub fn part_priority(self) -> &'static [Part] {
+        match self {
+            Role::Harvester | Role::Worker => &[Part::Work, Part::Carry, Part::Move],
+            Role::Carrier => &[Part::Carry, Part::Move],
+            Role::Defender => &[Part::Attack, Part::RangedAttack, Part::Heal, Part::Move, Part::Tough],
+            Role::Medic => &[Part::Heal, Part::Move, Part::Tough],
+            Role::Reserver => &[Part::Claim, Part::Move],
+        }
+    }
+
+    // whether creeps in this role move energy around
+    pub fn carries(self) -> bool {
+        matches!(self, Role::Carrier | Role::Worker)
+    }
+}
+
+// classify a creep by what its body is built for: anything with ATTACK is a defender (even if
+// it also brings HEAL for itself), HEAL without ATTACK is a medic, CLAIM is a reserver, and the
+// rest is down to whether it has WORK, CARRY or both
+pub fn infer_role(creep: &Creep) -> Role {
+    role_for_body(&creep.body().iter().map(|body| body.part()).collect::<Vec<_>>())
+}
+
+fn role_for_body(body: &[Part]) -> Role {
+    let has = |part: Part| body.contains(&part);
+    if has(Part::Attack) {
+        Role::Defender
+    } else if has(Part::Heal) {
+        Role::Medic
+    } else if has(Part::Claim) {
+        Role::Reserver
+    } else if has(Part::Carry) && has(Part::Work) {
+        Role::Worker
+    } else if has(Part::Carry) {
+        Role::Carrier
+    } else {
+        Role::Harvester
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attack_with_heal_is_a_defender() {
+        assert_eq!(role_for_body(&[Part::Tough, Part::Attack, Part::Heal, Part::Move]), Role::Defender);
+    }
+
+    #[test]
+    fn heal_is_a_medic() {
+        assert_eq!(role_for_body(&[Part::Heal, Part::Move]), Role::Medic);
+    }
+
+    #[test]
+    fn claim_is_a_reserver() {
+        assert_eq!(role_for_body(&[Part::Claim, Part::Move]), Role::Reserver);
+    }
+
+    #[test]
+    fn work_and_carry_is_a_worker() {
+        assert_eq!(role_for_body(&[Part::Work, Part::Carry, Part::Move]), Role::Worker);
+    }
+
+    #[test]
+    fn carry_is_a_carrier() {
+        assert_eq!(role_for_body(&[Part::Carry, Part::Move]), Role::Carrier);
+    }
+
+    #[test]
+    fn work_is_a_harvester() {
+        assert_eq!(role_for_body(&[Part::Work, Part::Work, Part::Move]), Role::Harvester);
+    }
+}
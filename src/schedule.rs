@@ -0,0 +1,48 @@
+use screeps::{game, objects::Room};
+
+// the expensive passes that don't need to run every tick, each on its own interval and offset
+// within it so they don't all land on the same tick; rooms are staggered too, so a pass over
+// several rooms is spread across a few ticks rather than all happening at once
+
+// how often each pass runs for each room, in ticks
+const REMOTE_SURVEY_INTERVAL: u32 = 10;
+const CONTAINERS_INTERVAL: u32 = 100;
+const MARKET_INTERVAL: u32 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    RemoteSurvey,
+    Containers,
+    Market,
+}
+
+impl Pass {
+    fn interval(self) -> u32 {
+        match self {
+            Pass::RemoteSurvey => REMOTE_SURVEY_INTERVAL,
+            Pass::Containers => CONTAINERS_INTERVAL,
+            Pass::Market => MARKET_INTERVAL,
+        }
+    }
+
+    // where in its interval the pass falls, picked so no two passes share a tick
+    fn offset(self) -> u32 {
+        match self {
+            Pass::RemoteSurvey => 3,
+            Pass::Containers => 37,
+            Pass::Market => 251,
+        }
+    }
+}
+
+// a room's name works as a steady offset for it, whatever order the rooms come in
+fn room_offset(room: &Room) -> u32 {
+    let name = room.name();
+    (name.x_coord() * 7 + name.y_coord() * 13).unsigned_abs()
+}
+
+// whether `pass` should run for `room` this tick
+pub fn due(pass: Pass, room: &Room) -> bool {
+    let interval = pass.interval();
+    game::time() % interval == (pass.offset() + room_offset(room)) % interval
+}
@@ -0,0 +1,110 @@
+use log::*;
+use screeps::{
+    constants::{find, Part, ResourceType},
+    enums::StructureObject,
+    objects::{Creep, Room},
+    prelude::*,
+};
+use wasm_bindgen::JsCast;
+
+use crate::{intents, memory, movement, remote, role::Role};
+
+// before a room has any containers, a worker with about as much WORK as CARRY gets more done
+// mining for itself than waiting on harvesters to drop energy it can pick up; these shuttle
+// between a source and the spawn on their own, without going through target assignment at all.
+// whether one is on its way to deposit is kept as `shuttling` in its memory, and setting
+// `Memory.rooms[name].shuttle = 0` turns the behaviour off for a room
+
+// WORK and CARRY counts at most this far apart count as a balanced body
+const BALANCE_TOLERANCE: u8 = 1;
+
+fn enabled(room: &Room) -> bool {
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "shuttle").is_none_or(|shuttle| shuttle > 0)
+}
+
+// whether the creep should run as a shuttle: a worker at home with a balanced body, in a room
+// that's yet to build any containers
+pub fn is_shuttle(creep: &Creep) -> bool {
+    if Role::of(creep) != Role::Worker || remote::remote_room(creep).is_some() {
+        return false;
+    }
+    let work = creep.get_active_bodyparts(Part::Work);
+    let carry = creep.get_active_bodyparts(Part::Carry);
+    if work == 0 || carry == 0 || work.abs_diff(carry) > BALANCE_TOLERANCE {
+        return false;
+    }
+    let Some(room) = creep.room() else {
+        return false;
+    };
+    enabled(&room) && !room.find(find::STRUCTURES, None).iter().any(|s| matches!(s, StructureObject::StructureContainer(_)))
+}
+
+pub fn run_shuttle(creep: &Creep) {
+    if creep.spawning() {
+        return;
+    }
+    let Some(room) = creep.room() else {
+        return;
+    };
+    let memory = creep.memory().unchecked_into();
+    let energy = creep.store().get_used_capacity(Some(ResourceType::Energy));
+    // harvest until full, then deposit until empty
+    let mut shuttling = memory::get_u32(&memory, "shuttling").unwrap_or(0) > 0;
+    if shuttling && energy == 0 {
+        shuttling = false;
+    } else if !shuttling && creep.store().get_free_capacity(Some(ResourceType::Energy)) == 0 {
+        shuttling = true;
+    }
+    memory::set_u32(&memory, "shuttling", shuttling as u32);
+
+    if shuttling {
+        deposit(creep, &room);
+    } else {
+        harvest(creep);
+    }
+}
+
+fn harvest(creep: &Creep) {
+    let Some(source) = creep.pos().find_closest_by_path(find::SOURCES_ACTIVE, None) else {
+        return;
+    };
+    if creep.pos().is_near_to(source.pos()) {
+        debug!("{}: shuttle harvesting", creep.name());
+        if let Err(e) = intents::act(creep, || creep.harvest(&source)) {
+            warn!("{}: shuttle couldn't harvest: {:?}", creep.name(), e);
+        }
+    } else {
+        let _ = movement::travel_to(creep, source.pos());
+    }
+}
+
+// the spawn and extensions come first, and with those full the energy goes into the controller
+fn deposit(creep: &Creep, room: &Room) {
+    let fill = room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .filter(|s| matches!(s, StructureObject::StructureSpawn(_) | StructureObject::StructureExtension(_)))
+        .filter(|s| s.as_has_store().is_some_and(|s| s.store().get_free_capacity(Some(ResourceType::Energy)) > 0))
+        .min_by_key(|s| creep.pos().get_range_to(s.pos()));
+    if let Some(structure) = fill {
+        if creep.pos().is_near_to(structure.pos()) {
+            debug!("{}: shuttle depositing", creep.name());
+            if let Some(transferable) = structure.as_transferable() {
+                if let Err(e) = intents::act(creep, || creep.transfer(transferable, ResourceType::Energy, None)) {
+                    warn!("{}: shuttle couldn't deposit: {:?}", creep.name(), e);
+                }
+            }
+        } else {
+            let _ = movement::travel_to(creep, structure.pos());
+        }
+    } else if let Some(controller) = room.controller() {
+        if creep.pos().in_range_to(controller.pos(), 3) {
+            debug!("{}: shuttle upgrading", creep.name());
+            if let Err(e) = intents::act(creep, || creep.upgrade_controller(&controller)) {
+                warn!("{}: shuttle couldn't upgrade: {:?}", creep.name(), e);
+            }
+        } else {
+            let _ = movement::travel_to(creep, controller.pos());
+        }
+    }
+}
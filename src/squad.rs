@@ -0,0 +1,202 @@
+use js_sys::{JsString, Object, Reflect};
+use log::*;
+use screeps::{
+    constants::{find, look, Part},
+    enums::StructureObject,
+    game,
+    local::Position,
+    objects::{Creep, Flag},
+    prelude::*,
+};
+use wasm_bindgen::JsCast;
+
+use crate::{defense, intents, memory, movement, role::Role};
+
+// attack squads: a few attackers and a medic that gather at a rally point and then go for their
+// objective together, rather than trickling into an enemy room one at a time to get picked
+// off. members carry `squad` (the squad's name) in their memory, and whether a squad has set
+// off is kept in `Memory.squads[name].advancing`. placing the `rally` and `attack` flags
+// starts one off; raids on the neighbours (see `harass`) are squads as well
+const FLAG_SQUAD: &str = "rally";
+const RALLY_FLAG: &str = "rally";
+const ATTACK_FLAG: &str = "attack";
+
+pub const SQUAD_ATTACKERS: usize = 2;
+const SQUAD_MEDICS: usize = 1;
+
+// members this close to the rally point count as assembled, and while advancing the leader
+// waits for anyone who falls further behind than this
+const FORMATION_RANGE: u32 = 3;
+
+fn flag(name: &str) -> Option<Flag> {
+    game::flags().get(name.to_string())
+}
+
+fn squad_memory(name: &str) -> Object {
+    memory::child(&memory::child(&screeps::memory::ROOT, "squads"), name)
+}
+
+// the squad a creep belongs to, if any; squad creeps are run here rather than being given
+// targets like everything else
+pub fn squad_of(creep: &Creep) -> Option<String> {
+    let memory = creep.memory();
+    if !memory.is_object() {
+        return None;
+    }
+    memory::get_string(&memory.unchecked_into(), "squad")
+}
+
+// add `squad` to the memory of a creep about to be spawned as a member of the `name` squad
+pub fn tag_memory(memory: &Object, name: &str) {
+    memory::set_string(memory, "squad", name);
+}
+
+pub fn members(name: &str) -> Vec<Creep> {
+    game::creeps().values().filter(|creep| squad_of(creep).as_deref() == Some(name)).collect()
+}
+
+pub fn advancing(name: &str) -> bool {
+    memory::get_u32(&squad_memory(name), "advancing").unwrap_or(0) > 0
+}
+
+// the next member the flag squad needs spawned, if there's a squad to form at all
+pub fn needed_member() -> Option<(Role, &'static str)> {
+    flag(RALLY_FLAG)?;
+    flag(ATTACK_FLAG)?;
+    next_member(FLAG_SQUAD).map(|role| (role, FLAG_SQUAD))
+}
+
+// the next member the `name` squad needs spawned to be at full strength
+pub fn next_member(name: &str) -> Option<Role> {
+    if advancing(name) {
+        // stragglers don't get replaced once the squad has set off
+        return None;
+    }
+    let members = members(name);
+    let count = |role: Role| members.iter().filter(|creep| Role::of(creep) == role).count();
+    if count(Role::Defender) < SQUAD_ATTACKERS {
+        Some(Role::Defender)
+    } else if count(Role::Medic) < SQUAD_MEDICS {
+        Some(Role::Medic)
+    } else {
+        None
+    }
+}
+
+pub fn body(role: Role, energy: u32) -> Vec<Part> {
+    match role {
+        Role::Medic => {
+            let units = (energy / (Part::Move.cost() + Part::Heal.cost())).min(10) as usize;
+            [Part::Move, Part::Heal].repeat(units)
+        }
+        _ => defense::defender_body(energy, true),
+    }
+}
+
+// the member the rest of the squad follows: its first attacker, if it has any left
+pub fn leader(members: &[Creep]) -> Option<&Creep> {
+    members.iter().find(|creep| Role::of(creep) == Role::Defender).or(members.first())
+}
+
+pub fn run_squad() {
+    match (flag(RALLY_FLAG), flag(ATTACK_FLAG)) {
+        (Some(rally), Some(attack)) => {
+            run(FLAG_SQUAD, rally.pos(), attack.pos());
+        }
+        _ => reset_if_wiped_out(FLAG_SQUAD),
+    }
+}
+
+// once the whole squad is gone, the next one can form
+pub fn reset_if_wiped_out(name: &str) {
+    if advancing(name) && members(name).is_empty() {
+        warn!("squad {} has been wiped out", name);
+        memory::set_u32(&squad_memory(name), "advancing", 0);
+    }
+}
+
+// run the `name` squad for the tick: gathering at `rally` until it's at full strength, then
+// following its leader to `objective`, fighting whatever it comes up against on the way. true
+// on the tick it sets off
+pub fn run(name: &str, rally: Position, objective: Position) -> bool {
+    let members = members(name);
+    let Some(leader) = leader(&members) else {
+        reset_if_wiped_out(name);
+        return false;
+    };
+    let mut advancing = advancing(name);
+    let mut set_off = false;
+    if !advancing {
+        let ready = members.iter().all(|creep| !creep.spawning());
+        let assembled = next_member(name).is_none() && ready && members.iter().all(|creep| creep.pos().in_range_to(rally, FORMATION_RANGE));
+        if assembled {
+            warn!("squad {} assembled with {} members, advancing on {:?}", name, members.len(), objective);
+            memory::set_u32(&squad_memory(name), "advancing", 1);
+            advancing = true;
+            set_off = true;
+        }
+    }
+
+    for creep in members.iter().filter(|creep| !creep.spawning()) {
+        fight(creep, &members, objective);
+        if !advancing {
+            if !creep.pos().in_range_to(rally, 1) {
+                let _ = movement::travel_to(creep, rally);
+            }
+        } else if creep.name() == leader.name() {
+            // only push on once everyone's caught up
+            let together = members.iter().all(|member| member.pos().room_name() == creep.pos().room_name() && member.pos().in_range_to(creep.pos(), FORMATION_RANGE));
+            if together {
+                let _ = movement::travel_to(creep, objective);
+            }
+        } else {
+            let _ = movement::travel_to(creep, leader.pos());
+        }
+    }
+    set_off
+}
+
+// break the squad up, taking `squad` out of its members' memory so they're free for other work
+pub fn disband(name: &str) {
+    for creep in members(name) {
+        let memory = creep.memory();
+        if memory.is_object() {
+            let _ = Reflect::delete_property(&memory.unchecked_into(), &JsString::from("squad"));
+        }
+    }
+    memory::set_u32(&squad_memory(name), "advancing", 0);
+}
+
+// attackers hit anything hostile next to them, or failing that whatever's standing on the
+// objective (which needn't be anyone's, like the containers another player mines into); medics
+// patch up whichever member is most hurt
+fn fight(creep: &Creep, members: &[Creep], objective: Position) {
+    if creep.get_active_bodyparts(Part::Attack) > 0 {
+        if let Some(hostile) = creep.pos().find_in_range(find::HOSTILE_CREEPS, 1).first() {
+            let _ = intents::act(creep, || creep.attack(hostile));
+        } else if let Some(structure) = creep.pos().find_in_range(find::HOSTILE_STRUCTURES, 1).first() {
+            if let Some(attackable) = structure.as_attackable() {
+                let _ = intents::act(creep, || creep.attack(attackable));
+            }
+        } else if creep.pos().is_near_to(objective) {
+            let structures = objective.look_for(look::STRUCTURES).unwrap_or_default();
+            let structure = structures.iter()
+                .filter(|s| !matches!(s, StructureObject::StructureController(_)))
+                .find_map(|s| s.as_attackable());
+            if let Some(attackable) = structure {
+                let _ = intents::act(creep, || creep.attack(attackable));
+            }
+        }
+    } else if creep.get_active_bodyparts(Part::Heal) > 0 {
+        let patient = members.iter()
+            .filter(|member| member.hits() < member.hits_max() && member.pos().room_name() == creep.pos().room_name())
+            .min_by_key(|member| member.hits() * 100 / member.hits_max());
+        if let Some(patient) = patient {
+            if creep.pos().is_near_to(patient.pos()) {
+                let _ = intents::act(creep, || creep.heal(patient));
+            } else if creep.pos().in_range_to(patient.pos(), 3) {
+                let _ = intents::act(creep, || creep.ranged_heal(patient));
+            }
+        }
+    }
+}
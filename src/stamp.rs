@@ -0,0 +1,167 @@
+use js_sys::Object;
+use log::*;
+use screeps::{
+    constants::{find, look, Terrain},
+    local::{Position, RoomCoordinate},
+    objects::{Room, RoomTerrain},
+    structure::StructureType,
+    prelude::*,
+};
+
+use crate::memory;
+
+// the base layout rooms get built to, put down as one block (a "stamp") around an anchor tile
+// rather than a structure at a time wherever there's space: storage in the middle with the
+// spawns, terminal and towers packed around it, extensions filling out the rest and roads
+// running through it all so every structure can be reached. where a room's stamp is anchored
+// is kept in `Memory.rooms[name].stamp` as `{ x, y }` once it's been chosen, so it gets built
+// up level by level in the same place; `stamp.failed` means nowhere in the room fits one
+
+// s spawn, S storage, T terminal, t tower, e extension, r road; the anchor is the middle tile
+const BUNKER: [&str; 9] = [
+    "reeereeer",
+    "erertrere",
+    "eereteree",
+    "erersrere",
+    "retsSster",
+    "ererTrere",
+    "eereteree",
+    "erertrere",
+    "reeereeer",
+];
+
+const BUNKER_RADIUS: i32 = 4;
+
+// nothing in the stamp goes this close to a source or the controller, so there's room to mine
+// and upgrade around them, the same as for ad-hoc placement
+const KEEP_CLEAR_RANGE: u32 = 2;
+
+fn structure_of(tile: char) -> Option<StructureType> {
+    match tile {
+        's' => Some(StructureType::Spawn),
+        'S' => Some(StructureType::Storage),
+        'T' => Some(StructureType::Terminal),
+        't' => Some(StructureType::Tower),
+        'e' => Some(StructureType::Extension),
+        'r' => Some(StructureType::Road),
+        _ => None,
+    }
+}
+
+// the offsets from the anchor of everything in the stamp, nearest the middle first, so each
+// level's new structures get built from the core outwards
+fn offsets() -> Vec<((i32, i32), StructureType)> {
+    let mut offsets = BUNKER.iter()
+        .enumerate()
+        .flat_map(|(row, line)| line.chars().enumerate().map(move |(column, tile)| ((column as i32 - BUNKER_RADIUS, row as i32 - BUNKER_RADIUS), tile)))
+        .filter_map(|(offset, tile)| structure_of(tile).map(|structure_type| (offset, structure_type)))
+        .collect::<Vec<_>>();
+    offsets.sort_by_key(|((dx, dy), _)| dx.abs().max(dy.abs()));
+    offsets
+}
+
+fn stamp_memory(room: &Room) -> Object {
+    memory::child(&memory::room(room.name()), "stamp")
+}
+
+// whether the whole stamp fits with its middle on `anchor`: clear of walls, the room edges and
+// the space around the sources and controller
+fn fits(anchor: Position, terrain: &RoomTerrain, keep_clear: &[Position]) -> bool {
+    offsets().iter().all(|(offset, _)| match anchor.checked_add(*offset) {
+        Ok(pos) => {
+            (2..=47).contains(&pos.x().u8()) && (2..=47).contains(&pos.y().u8())
+                && terrain.get(pos.x().u8(), pos.y().u8()) != Terrain::Wall
+                && keep_clear.iter().all(|clear| clear.get_range_to(pos) > KEEP_CLEAR_RANGE)
+        }
+        Err(_) => false,
+    })
+}
+
+// pick where to put the room's stamp: lined up so one of its spawn tiles falls on the spawn
+// the room already has if that fits, otherwise the tile it fits on nearest that spawn, or in a
+// room without one, the tile it fits on closest to the sources and controller
+fn choose_anchor(room: &Room) -> Option<Position> {
+    let terrain = room.get_terrain();
+    let keep_clear = room.find(find::SOURCES, None)
+        .iter()
+        .map(|source| source.pos())
+        .chain(room.controller().map(|controller| controller.pos()))
+        .collect::<Vec<_>>();
+    let spawn = room.find(find::MY_SPAWNS, None).first().map(|spawn| spawn.pos());
+
+    if let Some(spawn) = spawn {
+        let aligned = offsets().into_iter()
+            .filter(|(_, structure_type)| *structure_type == StructureType::Spawn)
+            .filter_map(|((dx, dy), _)| spawn.checked_add((-dx, -dy)).ok())
+            .find(|anchor| fits(*anchor, &terrain, &keep_clear));
+        if aligned.is_some() {
+            return aligned;
+        }
+    }
+
+    let mut best: Option<(u32, Position)> = None;
+    for x in 2 + BUNKER_RADIUS..=47 - BUNKER_RADIUS {
+        for y in 2 + BUNKER_RADIUS..=47 - BUNKER_RADIUS {
+            let anchor = Position::new(RoomCoordinate::new(x as u8).ok()?, RoomCoordinate::new(y as u8).ok()?, room.name());
+            let score = match spawn {
+                Some(spawn) => spawn.get_range_to(anchor),
+                None => keep_clear.iter().map(|pos| pos.get_range_to(anchor)).sum(),
+            };
+            if best.is_none_or(|(best, _)| score < best) && fits(anchor, &terrain, &keep_clear) {
+                best = Some((score, anchor));
+            }
+        }
+    }
+    best.map(|(_, anchor)| anchor)
+}
+
+// the anchor of the room's stamp, choosing one the first time it's asked for; None if no
+// stamp fits anywhere in the room, which leaves the planner placing things wherever it can
+pub fn anchor(room: &Room) -> Option<Position> {
+    let stamp_memory = stamp_memory(room);
+    if memory::get_u32(&stamp_memory, "failed").unwrap_or(0) > 0 {
+        return None;
+    }
+    let stored = memory::get_u32(&stamp_memory, "x")
+        .zip(memory::get_u32(&stamp_memory, "y"))
+        .and_then(|(x, y)| Some(Position::new(RoomCoordinate::new(x as u8).ok()?, RoomCoordinate::new(y as u8).ok()?, room.name())));
+    if stored.is_some() {
+        return stored;
+    }
+    match choose_anchor(room) {
+        Some(anchor) => {
+            info!("{}: laying the base out around {:?}", room.name(), anchor);
+            memory::set_u32(&stamp_memory, "x", anchor.x().u8().into());
+            memory::set_u32(&stamp_memory, "y", anchor.y().u8().into());
+            Some(anchor)
+        }
+        None => {
+            warn!("{}: no room for a base layout anywhere, placing structures wherever they fit", room.name());
+            memory::set_u32(&stamp_memory, "failed", 1);
+            None
+        }
+    }
+}
+
+// every tile of the room's stamp, with what belongs on it
+pub fn tiles(room: &Room) -> Vec<(Position, StructureType)> {
+    let Some(anchor) = anchor(room) else {
+        return Vec::new();
+    };
+    offsets().into_iter()
+        .filter_map(|(offset, structure_type)| anchor.checked_add(offset).ok().map(|pos| (pos, structure_type)))
+        .collect()
+}
+
+// the tiles of the room's stamp still waiting on a `structure_type`, with nothing built or
+// planned on them yet, in the order they should be built
+pub fn free_slots(room: &Room, structure_type: StructureType) -> Vec<Position> {
+    tiles(room).into_iter()
+        .filter(|(_, slot)| *slot == structure_type)
+        .map(|(pos, _)| pos)
+        .filter(|pos| {
+            pos.look_for(look::STRUCTURES).is_ok_and(|s| s.is_empty())
+                && pos.look_for(look::CONSTRUCTION_SITES).is_ok_and(|s| s.is_empty())
+        })
+        .collect()
+}
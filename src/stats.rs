@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use js_sys::{JsString, Object, Reflect};
+use screeps::{find, game, prelude::*};
+use wasm_bindgen::JsValue;
+
+use crate::role::Role;
+
+// a compact, machine-readable snapshot of each room written to `Memory.stats` every tick,
+// meant for external dashboards/scripts - keep keys short since this gets serialized along
+// with the rest of memory every tick
+pub fn record_stats() {
+    let mut creep_counts: HashMap<(String, Role), u32> = HashMap::new();
+    for creep in game::creeps().values() {
+        if let Some(room) = creep.room() {
+            *creep_counts.entry((room.name().to_string(), Role::of(&creep))).or_default() += 1;
+        }
+    }
+
+    let rooms = Object::new();
+    for room in game::rooms().values() {
+        let controller = match room.controller() {
+            Some(controller) if controller.my() => controller,
+            _ => continue,
+        };
+        let name = room.name().to_string();
+        // keyed by the role's name prefix, eg `harv`
+        let creeps = Object::new();
+        for role in Role::ALL {
+            let count = creep_counts.get(&(name.clone(), role)).copied().unwrap_or(0);
+            set(&creeps, role.prefix().trim_end_matches('-'), count);
+        }
+
+        let room_stats = Object::new();
+        set(&room_stats, "e", room.energy_available());
+        set(&room_stats, "ec", room.energy_capacity_available());
+        set(&room_stats, "rcl", controller.level().into());
+        set(&room_stats, "prog", controller.progress().unwrap_or(0));
+        set(&room_stats, "hostiles", room.find(find::HOSTILE_CREEPS, None).len() as u32);
+        let _ = Reflect::set(&room_stats, &JsString::from("creeps"), &creeps);
+        let _ = Reflect::set(&rooms, &JsString::from(name), &room_stats);
+    }
+
+    let stats = Object::new();
+    set(&stats, "time", game::time());
+    let _ = Reflect::set(&stats, &JsString::from("cpu"), &JsValue::from(game::cpu::get_used()));
+    set(&stats, "bucket", game::cpu::bucket() as u32);
+    let _ = Reflect::set(&stats, &JsString::from("rooms"), &rooms);
+
+    let _ = Reflect::set(&screeps::memory::ROOT, &JsString::from("stats"), &stats);
+}
+
+fn set(target: &Object, key: &str, value: u32) {
+    let _ = Reflect::set(target, &JsString::from(key), &JsValue::from(value));
+}
@@ -0,0 +1,120 @@
+use js_sys::{JsString, Reflect};
+use log::*;
+use screeps::objects::Room;
+
+use crate::{memory, role::Role};
+
+// how spawns trade off the economy against defense, read from `Memory.spawnStrategy` so it can
+// be changed from the console without a redeploy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpawnStrategy {
+    // keep the economy going first, and only defend once it has what it needs
+    EconomyFirst,
+    // always keep a standing pair of defenders around, economy comes after
+    DefenseFirst,
+    // a defender and then a medic during an invasion, economy the rest of the time
+    Balanced,
+}
+
+// the standing defenders `DefenseFirst` keeps alive whether or not there's anything to fight
+const STANDING_DEFENDERS: usize = 2;
+
+impl SpawnStrategy {
+    // the defenders a room keeps around with nothing to fight
+    pub fn standing_defenders(self) -> usize {
+        match self {
+            SpawnStrategy::DefenseFirst => STANDING_DEFENDERS,
+            SpawnStrategy::EconomyFirst | SpawnStrategy::Balanced => 0,
+        }
+    }
+
+    pub fn current() -> SpawnStrategy {
+        let value = Reflect::get(&screeps::memory::ROOT, &JsString::from("spawnStrategy"))
+            .ok()
+            .and_then(|value| value.as_string());
+        match value.as_deref() {
+            Some("economy") => SpawnStrategy::EconomyFirst,
+            Some("defense") => SpawnStrategy::DefenseFirst,
+            Some("balanced") | None => SpawnStrategy::Balanced,
+            Some(other) => {
+                warn!("unknown spawn strategy {:?}, using balanced", other);
+                SpawnStrategy::Balanced
+            }
+        }
+    }
+}
+
+// whether a spawn is growing the room, which can afford to wait for the energy to build the
+// biggest body the room's capacity allows, or replacing something the room can't do without,
+// which has to go out with whatever energy is available right now
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpawnMode {
+    Growth,
+    Replacement,
+}
+
+impl SpawnMode {
+    // replacements are for losing the last of a role the economy runs on, or for an economy
+    // that's stalled outright
+    pub fn of(critical: bool, stalled: bool) -> SpawnMode {
+        if critical || stalled { SpawnMode::Replacement } else { SpawnMode::Growth }
+    }
+
+    // the energy to size a body from; a growth body gets spawned once there's enough energy
+    // available for it
+    pub fn body_energy(self, available: u32, capacity: u32) -> u32 {
+        match self {
+            SpawnMode::Growth => capacity,
+            SpawnMode::Replacement => available,
+        }
+    }
+}
+
+// energy a room holds back when spawning anything it could do without, so a minimal emergency
+// harvester (WORK, CARRY, MOVE) can go out straight away if the last one dies;
+// `Memory.rooms[name].spawnReserve` overrides it
+const SPAWN_RESERVE: u32 = 200;
+
+pub fn spawn_reserve(room: &Room) -> u32 {
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "spawnReserve").unwrap_or(SPAWN_RESERVE)
+}
+
+// whether spawning a body costing `cost` would eat into the reserve. a body so big the reserve
+// could never be left over on top of it only has to wait for a full room, otherwise nothing
+// that size would ever get spawned
+pub fn eats_reserve(room: &Room, cost: u32) -> bool {
+    let reserve = spawn_reserve(room).min(room.energy_capacity_available().saturating_sub(cost));
+    room.energy_available() < cost + reserve
+}
+
+// what a spawn's room currently has and needs, as far as choosing what to spawn goes
+pub struct RoomState {
+    pub hostiles: usize,
+    pub defenders: usize,
+    pub medics: usize,
+    // the economic role the room wants more of, if any
+    pub economy: Option<Role>,
+}
+
+// the roles the room wants spawned, most important first
+pub fn spawn_order(strategy: SpawnStrategy, state: &RoomState) -> Vec<Role> {
+    let mut defense = Vec::new();
+    let wanted_defenders = match strategy {
+        SpawnStrategy::DefenseFirst => STANDING_DEFENDERS,
+        _ if state.hostiles > 0 => 1,
+        _ => 0,
+    };
+    if state.defenders < wanted_defenders {
+        defense.push(Role::Defender);
+    }
+    if state.hostiles > 0 && state.medics == 0 {
+        defense.push(Role::Medic);
+    }
+    let economy = state.economy.into_iter();
+
+    match strategy {
+        SpawnStrategy::EconomyFirst => economy.chain(defense).collect(),
+        SpawnStrategy::DefenseFirst | SpawnStrategy::Balanced => defense.into_iter().chain(economy).collect(),
+    }
+}
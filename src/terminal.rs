@@ -0,0 +1,171 @@
+use js_sys::{JsString, Object, Reflect};
+use log::*;
+use screeps::{
+    constants::ResourceType,
+    game,
+    local::RoomName,
+    objects::Room,
+    prelude::*,
+};
+use wasm_bindgen::JsValue;
+
+use crate::{market, memory};
+
+// resources passed between our rooms by terminal: a room that needs something puts in a
+// request as `Memory.terminalRequests[room] = {resource: "energy", amount: 20000}`, by hand or
+// on its own once its storage runs low on energy, and the other rooms with some to spare send
+// it over a batch at a time until the request has been met. a request nobody can fill is given
+// up on after `REQUEST_EXPIRY_TICKS`. haulers keep each terminal stocked from its storage with
+// what it needs for sending and selling, and put anything it's been sent beyond that away in
+// the storage
+
+// a room with a terminal asks for energy once its storage has less than this in it, and asks
+// for `ENERGY_REQUEST` of it
+const LOW_ENERGY: u32 = 20_000;
+const ENERGY_REQUEST: u32 = 20_000;
+
+// at most this much goes out in one send
+const SEND_BATCH: u32 = 5_000;
+
+// a terminal never sends away energy it'd need to keep this much for itself, and for paying
+// for its own sends
+const TERMINAL_ENERGY_KEEP: u32 = 10_000;
+
+const REQUEST_EXPIRY_TICKS: u32 = 5_000;
+
+// how far over its target a terminal's energy has to get before it's taken back to the
+// storage, so haulers aren't carrying the same energy back and forth
+const TERMINAL_ENERGY_SLACK: u32 = 5_000;
+
+fn requests() -> Object {
+    memory::child(&screeps::memory::ROOT, "terminalRequests")
+}
+
+fn low_on_energy(room: &Room) -> bool {
+    room.storage().is_some_and(|storage| storage.store().get_used_capacity(Some(ResourceType::Energy)) < LOW_ENERGY)
+}
+
+// the energy the terminal should have in it: enough to send a batch on top of what it keeps for
+// itself, plus whatever the market's selling, none of which a room short on energy holds back
+fn energy_target(room: &Room) -> u32 {
+    if low_on_energy(room) {
+        return 0;
+    }
+    TERMINAL_ENERGY_KEEP + SEND_BATCH + market::energy_for_sale(room)
+}
+
+fn terminal_energy(room: &Room) -> Option<u32> {
+    room.storage()?;
+    Some(room.terminal()?.store().get_used_capacity(Some(ResourceType::Energy)))
+}
+
+// how much energy the terminal needs bringing over from the storage
+pub fn shortfall(room: &Room) -> u32 {
+    terminal_energy(room).map_or(0, |energy| energy_target(room).saturating_sub(energy))
+}
+
+// how much energy the terminal has that should go back into the storage
+pub fn surplus(room: &Room) -> u32 {
+    terminal_energy(room)
+        .map(|energy| energy.saturating_sub(energy_target(room)))
+        .filter(|surplus| *surplus > TERMINAL_ENERGY_SLACK)
+        .unwrap_or(0)
+}
+
+// ask the other rooms for `amount` of `resource`, unless the room already has a request in
+pub fn request(room: &Room, resource: ResourceType, amount: u32) {
+    let requests = requests();
+    let key = JsString::from(room.name().to_string());
+    if Reflect::get(&requests, &key).is_ok_and(|request| request.is_object()) {
+        return;
+    }
+    info!("{}: asking the other rooms for {} {:?}", room.name(), amount, resource);
+    let request = memory::child(&requests, &room.name().to_string());
+    let _ = Reflect::set(&request, &JsString::from("resource"), &JsValue::from(resource));
+    memory::set_u32(&request, "amount", amount);
+    memory::set_u32(&request, "since", game::time());
+}
+
+fn drop_request(room_name: &str) {
+    let _ = Reflect::delete_property(&requests(), &JsString::from(room_name));
+}
+
+// put in this room's own requests, and send whatever it can spare towards the others'
+pub fn run_terminal(room: &Room) {
+    let Some(terminal) = room.terminal() else {
+        return;
+    };
+    if !terminal.my() {
+        return;
+    }
+    if low_on_energy(room) {
+        request(room, ResourceType::Energy, ENERGY_REQUEST);
+    }
+
+    let requests = requests();
+    let own = room.name().to_string();
+    // a room waiting on something itself doesn't give anything away
+    let asking = memory::keys(&requests).contains(&own);
+    for requester in memory::keys(&requests) {
+        let request = memory::child(&requests, &requester);
+        let resource = Reflect::get(&request, &JsString::from("resource")).ok().and_then(|value| ResourceType::from_js_value(&value));
+        let (Some(resource), Some(amount)) = (resource, memory::get_u32(&request, "amount")) else {
+            warn!("dropping malformed terminal request for {}", requester);
+            drop_request(&requester);
+            continue;
+        };
+        if requester == own {
+            // keeping an eye on our own request, to give up on it if nobody's answering
+            if game::time() > memory::get_u32(&request, "since").unwrap_or(0) + REQUEST_EXPIRY_TICKS {
+                warn!("{}: no room could send the {} {:?} it asked for, giving up", own, amount, resource);
+                drop_request(&requester);
+            }
+            continue;
+        }
+        if amount == 0 {
+            drop_request(&requester);
+            continue;
+        }
+        if asking || terminal.cooldown() > 0 {
+            continue;
+        }
+        let Ok(destination) = requester.parse::<RoomName>() else {
+            warn!("dropping terminal request for unknown room {}", requester);
+            drop_request(&requester);
+            continue;
+        };
+
+        let energy = terminal.store().get_used_capacity(Some(ResourceType::Energy));
+        let spare = match resource {
+            ResourceType::Energy => energy.saturating_sub(TERMINAL_ENERGY_KEEP),
+            _ => terminal.store().get_used_capacity(Some(resource)),
+        };
+        let mut batch = amount.min(SEND_BATCH).min(spare);
+        let mut cost = game::market::calc_transaction_cost(batch, &JsString::from(own.as_str()), &JsString::from(requester.as_str()));
+        if resource == ResourceType::Energy {
+            // the send is paid for out of the same energy, so send less to cover it
+            batch = batch.saturating_sub(cost);
+            cost = game::market::calc_transaction_cost(batch, &JsString::from(own.as_str()), &JsString::from(requester.as_str()));
+        } else if cost > energy {
+            continue;
+        }
+        if batch == 0 {
+            continue;
+        }
+        match terminal.send(resource, batch, destination, None) {
+            Ok(()) => {
+                info!("{}: sent {} {:?} to {} for {} energy", own, batch, resource, requester, cost);
+                let left = amount.saturating_sub(batch);
+                if left == 0 {
+                    info!("{}: request for {:?} has been met", requester, resource);
+                    drop_request(&requester);
+                } else {
+                    memory::set_u32(&request, "amount", left);
+                }
+                // one send per terminal per cooldown
+                return;
+            }
+            Err(e) => warn!("{}: couldn't send {:?} to {}: {:?}", own, resource, requester, e),
+        }
+    }
+}
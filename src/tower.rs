@@ -0,0 +1,303 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use log::*;
+use screeps::{
+    constants::{
+        Part, ResourceType, HEAL_POWER, RANGED_HEAL_POWER, TOWER_ENERGY_COST, TOWER_FALLOFF, TOWER_FALLOFF_RANGE,
+        TOWER_OPTIMAL_RANGE, TOWER_POWER_ATTACK, TOWER_POWER_HEAL,
+    },
+    enums::StructureObject,
+    find, game,
+    local::{ObjectId, Position, RoomName},
+    objects::{Creep, Room, StructureTower},
+    prelude::*,
+};
+
+use crate::{defense, links, upgrading};
+
+// where each hostile creep was last tick, so we can tell which way it's heading
+thread_local! {
+    static HOSTILE_POSITIONS: RefCell<HashMap<ObjectId<Creep>, Position>> = RefCell::new(HashMap::new());
+}
+
+// each of our creeps' hits last tick, and roughly how many it's been losing per tick lately
+// (net of whatever healing it's been getting), so towers can tell which creeps are under
+// sustained fire
+thread_local! {
+    static FRIENDLY_HITS: RefCell<HashMap<ObjectId<Creep>, (u32, u32)>> = RefCell::new(HashMap::new());
+}
+
+// hostiles this close to the edge of the room and heading towards it are most likely on their
+// way out, and not worth spending tower energy on
+const EDGE_DISTANCE: u8 = 2;
+
+// towers hold on to this much energy rather than spending it on repairs, so there's always
+// something left to shoot with
+const TOWER_ENERGY_RESERVE: u32 = 100;
+
+// creeps only get healed ahead of repairs once they're below this percentage of their hits
+// (or under sustained fire), so a barely scratched creep doesn't keep a decaying road from
+// being repaired
+const CREEP_HEAL_BELOW_PERCENT: u32 = 50;
+
+// during combat one hauler per room is taken out of the general pool to keep the towers topped
+// up from the room's energy buffer (the storage, its link, or the controller's container), so
+// they don't run dry while every other hauler is busy elsewhere; towers below this percentage
+// of their energy get fed first
+const TOWER_FEED_BELOW_PERCENT: u32 = 80;
+
+fn towers(room: &Room) -> Vec<StructureTower> {
+    room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .filter_map(|s| match s {
+            StructureObject::StructureTower(tower) => Some(tower),
+            _ => None,
+        })
+        .collect()
+}
+
+// the closest of the room's energy buffers to `near` with any energy in it to feed towers from
+pub fn energy_buffer(room: &Room, near: Position) -> Option<StructureObject> {
+    let mut buffers = Vec::new();
+    if let Some(storage) = room.storage() {
+        buffers.push(StructureObject::StructureStorage(storage));
+    }
+    if let Some(link) = links::storage_link(room) {
+        buffers.push(StructureObject::StructureLink(link));
+    }
+    if let Some(container) = room.controller().and_then(|controller| upgrading::controller_container(&controller)) {
+        buffers.push(StructureObject::StructureContainer(container));
+    }
+    buffers.into_iter()
+        .filter(|s| s.as_has_store().is_some_and(|s| s.store().get_used_capacity(Some(ResourceType::Energy)) > 0))
+        .min_by_key(|s| near.get_range_to(s.pos()))
+}
+
+// whether the room's fighting and has towers and a buffer to feed them from
+pub fn needs_feeding(room: &Room) -> bool {
+    if room.find(find::HOSTILE_CREEPS, None).is_empty() {
+        return false;
+    }
+    let towers = towers(room);
+    towers.first().is_some_and(|tower| energy_buffer(room, tower.pos()).is_some())
+}
+
+// the tower to feed next: the emptiest below `TOWER_FEED_BELOW_PERCENT`, or failing that the
+// emptiest that isn't full
+pub fn hungriest_tower(room: &Room) -> Option<StructureTower> {
+    let percent = |tower: &StructureTower| tower.store().get_used_capacity(Some(ResourceType::Energy)) * 100 / tower.store().get_capacity(Some(ResourceType::Energy)).max(1);
+    let towers = towers(room).into_iter().filter(|tower| tower.store().get_free_capacity(Some(ResourceType::Energy)) > 0).collect::<Vec<_>>();
+    towers.iter()
+        .filter(|tower| percent(tower) < TOWER_FEED_BELOW_PERCENT)
+        .min_by_key(|tower| percent(tower))
+        .or_else(|| towers.iter().min_by_key(|tower| percent(tower)))
+        .cloned()
+}
+
+fn distance_to_edge(pos: Position) -> u8 {
+    let (x, y) = (pos.x().u8(), pos.y().u8());
+    x.min(49 - x).min(y).min(49 - y)
+}
+
+// the damage one tower does to something `range` away
+fn tower_damage(range: u32) -> u32 {
+    let (optimal, falloff) = (TOWER_OPTIMAL_RANGE as u32, TOWER_FALLOFF_RANGE as u32);
+    let past_optimal = range.clamp(optimal, falloff) - optimal;
+    let penalty = TOWER_POWER_ATTACK as f64 * TOWER_FALLOFF * past_optimal as f64 / (falloff - optimal) as f64;
+    TOWER_POWER_ATTACK - penalty as u32
+}
+
+// the healing one tower does on something `range` away
+fn tower_heal(range: u32) -> u32 {
+    let (optimal, falloff) = (TOWER_OPTIMAL_RANGE as u32, TOWER_FALLOFF_RANGE as u32);
+    let past_optimal = range.clamp(optimal, falloff) - optimal;
+    let penalty = TOWER_POWER_HEAL as f64 * TOWER_FALLOFF * past_optimal as f64 / (falloff - optimal) as f64;
+    TOWER_POWER_HEAL - penalty as u32
+}
+
+// bring every creep's damage rate up to date, each tick's loss counting for a quarter so one
+// big hit doesn't dominate; creeps we can't see any more are dropped
+fn track_friendly_hits() {
+    FRIENDLY_HITS.with(|hits_refcell| {
+        let mut hits = hits_refcell.borrow_mut();
+        let mut current = HashMap::new();
+        for creep in game::creeps().values() {
+            if let Some(id) = creep.try_id() {
+                let now = creep.hits();
+                let incoming = hits.get(&id).map_or(0, |(last, incoming)| (incoming * 3 + last.saturating_sub(now)) / 4);
+                current.insert(id, (now, incoming));
+            }
+        }
+        *hits = current;
+    });
+}
+
+fn incoming_damage(creep: &Creep) -> u32 {
+    creep.try_id().map_or(0, |id| FRIENDLY_HITS.with(|hits| hits.borrow().get(&id).map_or(0, |(_, incoming)| *incoming)))
+}
+
+// the creep the towers should heal this tick, if any: the one taking the most sustained damage
+// that the towers can still out-heal, and failing that the most hurt below
+// `CREEP_HEAL_BELOW_PERCENT`. a creep losing more per tick than all the towers can heal is a
+// lost cause, and healing it would just be energy thrown away
+fn heal_target(room: &Room) -> Option<Creep> {
+    let towers = room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .filter_map(|s| match s {
+            StructureObject::StructureTower(tower) if tower.store().get_used_capacity(Some(ResourceType::Energy)) >= TOWER_ENERGY_COST => Some(tower),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let healing = |creep: &Creep| towers.iter().map(|tower| tower_heal(tower.pos().get_range_to(creep.pos()))).sum::<u32>();
+    let damaged = room.find(find::MY_CREEPS, None)
+        .into_iter()
+        .filter(|creep| creep.hits() < creep.hits_max())
+        .filter(|creep| {
+            let saveable = incoming_damage(creep) < healing(creep);
+            if !saveable {
+                debug!("{}: towers can't keep up with the damage on {}, not healing it", room.name(), creep.name());
+            }
+            saveable
+        })
+        .collect::<Vec<_>>();
+    let under_fire = damaged.iter().filter(|creep| incoming_damage(creep) > 0).max_by_key(|creep| incoming_damage(creep));
+    if let Some(creep) = under_fire {
+        return Some(creep.clone());
+    }
+    damaged.into_iter()
+        .filter(|creep| creep.hits() * 100 / creep.hits_max() < CREEP_HEAL_BELOW_PERCENT)
+        .min_by_key(|creep| creep.hits() * 100 / creep.hits_max())
+}
+
+// roughly how much `target` can be healed each tick by the hostiles around it (itself
+// included), going by their HEAL parts and ignoring boosts
+fn heal_on(target: &Creep, hostiles: &[Creep]) -> u32 {
+    hostiles.iter()
+        .map(|healer| {
+            let parts = healer.get_active_bodyparts(Part::Heal) as u32;
+            match healer.pos().get_range_to(target.pos()) {
+                0..=1 => parts * HEAL_POWER,
+                2..=3 => parts * RANGED_HEAL_POWER,
+                _ => 0,
+            }
+        })
+        .sum()
+}
+
+// the hostile every tower in the room fires at this tick. anything the towers can out-damage
+// the healing on is fair game, siphoners first and then whatever's closest to dying; if the
+// healing on everything is more than the towers can do, there's no point in chipping at an
+// attacker it'll all be healed back on, so go for the biggest healer instead
+fn focus_target(room: &Room, leaving: &[ObjectId<Creep>]) -> Option<Creep> {
+    let hostiles = room.find(find::HOSTILE_CREEPS, None);
+    for hostile in hostiles.iter().filter(|hostile| hostile.try_id().is_some_and(|id| leaving.contains(&id))) {
+        debug!("Tower ignoring hostile creep leaving at {:?}", hostile.pos());
+    }
+    // against a drain attack, bait is only worth shooting once it's right up against our
+    // buildings, and everything with more fight in it than bait still gets shot as usual
+    let draining = defense::drain_suspected(room);
+    let candidates = hostiles.iter()
+        .filter(|hostile| !hostile.try_id().is_some_and(|id| leaving.contains(&id)))
+        .filter(|hostile| !draining || !defense::is_bait(hostile) || !hostile.pos().find_in_range(find::MY_STRUCTURES, 1).is_empty())
+        .collect::<Vec<_>>();
+    let towers = room.find(find::MY_STRUCTURES, None)
+        .into_iter()
+        .filter_map(|s| match s {
+            StructureObject::StructureTower(tower) if tower.store().get_used_capacity(Some(ResourceType::Energy)) >= TOWER_ENERGY_COST => Some(tower),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let damage = |target: &Creep| towers.iter().map(|tower| tower_damage(tower.pos().get_range_to(target.pos()))).sum::<u32>();
+
+    let beatable = candidates.iter()
+        .filter(|hostile| damage(hostile) > heal_on(hostile, &hostiles))
+        .min_by_key(|hostile| (!defense::is_siphoner(hostile), hostile.hits()));
+    if let Some(target) = beatable {
+        return Some((*target).clone());
+    }
+    let healer = candidates.iter()
+        .filter(|hostile| hostile.get_active_bodyparts(Part::Heal) > 0)
+        .max_by_key(|hostile| hostile.get_active_bodyparts(Part::Heal));
+    if let Some(healer) = healer {
+        debug!("{}: towers can't out-damage the healing, focusing the healer at {:?}", room.name(), healer.pos());
+        return Some((*healer).clone());
+    }
+    candidates.into_iter().min_by_key(|hostile| hostile.hits()).cloned()
+}
+
+pub fn run_towers() {
+    let leaving = HOSTILE_POSITIONS.with(|positions_refcell| {
+        let mut positions = positions_refcell.borrow_mut();
+        let mut current = HashMap::new();
+        let mut leaving = Vec::new();
+        for room in game::rooms().values() {
+            for hostile in room.find(find::HOSTILE_CREEPS, None) {
+                if let Some(id) = hostile.try_id() {
+                    let pos = hostile.pos();
+                    if let Some(last) = positions.get(&id) {
+                        if distance_to_edge(pos) <= EDGE_DISTANCE && distance_to_edge(pos) < distance_to_edge(*last) {
+                            leaving.push(id);
+                        }
+                    }
+                    current.insert(id, pos);
+                }
+            }
+        }
+        // only keep the creeps we can still see, so this doesn't grow forever
+        *positions = current;
+        leaving
+    });
+
+    track_friendly_hits();
+
+    // all the towers in a room fire on the same target, picked once per room, and the same
+    // goes for the creep they heal
+    let mut focus: HashMap<RoomName, Option<Creep>> = HashMap::new();
+    let mut patients: HashMap<RoomName, Option<Creep>> = HashMap::new();
+    for tower in game::structures().values() {
+        if let StructureObject::StructureTower(tower) = tower {
+            let available_energy = tower.store().get_used_capacity(Some(ResourceType::Energy));
+
+            let room = tower.room().unwrap();
+            let target = focus.entry(room.name()).or_insert_with(|| focus_target(&room, &leaving));
+            if let Some(target) = target {
+                // Attack if in range
+                if tower.pos().in_range_to(target.pos(), 20) {
+                    let _ = tower.attack(target);
+                    debug!("Tower attacking hostile creep at {:?}", target.pos());
+                }
+            } else {
+                // the most hurt of our creeps, by how much of its hits it has left
+                let damaged_creep = tower.room().unwrap().find(find::MY_CREEPS, None)
+                    .into_iter()
+                    .filter(|creep| creep.hits() < creep.hits_max())
+                    .min_by_key(|creep| creep.hits() * 100 / creep.hits_max());
+                let patient = patients.entry(room.name()).or_insert_with(|| heal_target(&room));
+
+                // towers can repair anywhere in the room, just less effectively further out, so
+                // look at everything rather than only what's close by, and go for whatever has
+                // the least of its hits left against its repair ceiling, ramparts included
+                let structure = if available_energy > TOWER_ENERGY_RESERVE {
+                    tower.room().unwrap().find(find::STRUCTURES, None)
+                        .into_iter()
+                        .filter(defense::needs_repair)
+                        .min_by_key(|s| s.as_structure().hits() as u64 * 100 / defense::repair_ceiling(s).unwrap_or(1).max(1) as u64)
+                } else {
+                    None
+                };
+
+                if let Some(creep) = patient {
+                    let _ = tower.heal(creep);
+                    debug!("Tower healing damaged creep at {:?}", creep.pos());
+                } else if let Some(structure) = structure {
+                    let _ = tower.repair(structure.as_repairable().unwrap());
+                    debug!("Tower repairing damaged structure at {:?}", structure.pos());
+                } else if let Some(creep) = damaged_creep {
+                    // nothing to repair, so top off the scratched ones too
+                    let _ = tower.heal(&creep);
+                    debug!("Tower healing damaged creep at {:?}", creep.pos());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use screeps::{
+    constants::{look, ResourceType},
+    enums::StructureObject,
+    local::{Position, RoomCoordinate, RoomName},
+    objects::{Room, StructureContainer, StructureController, StructureLink},
+    prelude::*,
+};
+
+use crate::{links, memory};
+
+// the controller's supply chain, getting energy to the upgraders with as little walking as
+// possible: with a controller link it's storage link -> controller link -> upgraders, the
+// links doing the hauling; without one it's haulers -> controller container -> upgraders;
+// and in a room with neither the upgraders go and fetch their own energy like anyone else
+pub enum ControllerSupply {
+    Link(StructureLink),
+    Container(StructureContainer),
+    Direct,
+}
+
+// where the controller's container is, as `plan_containers` placed it (or found it already
+// there), kept as `Memory.rooms[name].controllerContainer`; a source's container can be just as
+// close to the controller, so being nearby isn't enough to go on
+pub fn controller_container_pos(room: RoomName) -> Option<Position> {
+    let container_memory = memory::child(&memory::room(room), "controllerContainer");
+    let (x, y) = memory::get_u32(&container_memory, "x").zip(memory::get_u32(&container_memory, "y"))?;
+    Some(Position::new(RoomCoordinate::new(x as u8).ok()?, RoomCoordinate::new(y as u8).ok()?, room))
+}
+
+pub fn note_controller_container(pos: Position) {
+    let container_memory = memory::child(&memory::room(pos.room_name()), "controllerContainer");
+    memory::set_u32(&container_memory, "x", pos.x().u8().into());
+    memory::set_u32(&container_memory, "y", pos.y().u8().into());
+}
+
+pub fn controller_container(controller: &StructureController) -> Option<StructureContainer> {
+    controller_container_pos(controller.pos().room_name())?
+        .look_for(look::STRUCTURES)
+        .ok()?
+        .into_iter()
+        .find_map(|s| match s {
+            StructureObject::StructureContainer(container) => Some(container),
+            _ => None,
+        })
+}
+
+pub fn controller_supply(controller: &StructureController) -> ControllerSupply {
+    if let Some(link) = links::controller_link(controller) {
+        ControllerSupply::Link(link)
+    } else if let Some(container) = controller_container(controller) {
+        ControllerSupply::Container(container)
+    } else {
+        ControllerSupply::Direct
+    }
+}
+
+// whether this is the container the upgraders work from, which haulers fill rather than
+// empty
+pub fn is_controller_container(room: &Room, container: &StructureContainer) -> bool {
+    controller_container_pos(room.name()) == Some(container.pos())
+}
+
+// where haulers should drop energy off to keep the upgraders going, if it has room for more:
+// the link by the storage that feeds the controller link, or the controller's container
+pub fn supply_drop(room: &Room) -> Option<Position> {
+    let controller = room.controller()?;
+    match controller_supply(&controller) {
+        ControllerSupply::Link(_) => links::storage_link(room)
+            .filter(|link| link.store().get_free_capacity(Some(ResourceType::Energy)) > 0)
+            .map(|link| link.pos()),
+        ControllerSupply::Container(container) => (container.store().get_free_capacity(Some(ResourceType::Energy)) > 0).then(|| container.pos()),
+        ControllerSupply::Direct => None,
+    }
+}
@@ -0,0 +1,149 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use screeps::{
+    game,
+    local::Position,
+    objects::{LineStyle, RoomVisual, TextStyle},
+    prelude::*,
+};
+
+use crate::{memory, role::Role};
+
+// debugging overlays drawn into the rooms, only while `Memory.visuals` is set to 1 since they
+// cost CPU every tick
+
+// the colour each role's label is drawn in
+const ROLE_COLORS: [(Role, &str); 6] = [
+    (Role::Harvester, "#ffdd00"),
+    (Role::Carrier, "#3399ff"),
+    (Role::Worker, "#33cc33"),
+    (Role::Defender, "#ff3333"),
+    (Role::Medic, "#ffffff"),
+    (Role::Reserver, "#cc66ff"),
+];
+
+// where each creep last picked up energy from, and the energy that's been moved between each
+// pair of places (source to container, container to spawn, ...) as of the last tick any moved,
+// decayed as it goes so it tracks the recent rate; flows are drawn as one arrow per pair however
+// many creeps are running it
+thread_local! {
+    static PICKED_UP: RefCell<HashMap<String, Position>> = RefCell::new(HashMap::new());
+    static FLOWS: RefCell<HashMap<(Position, Position), (u32, u32)>> = RefCell::new(HashMap::new());
+}
+
+// each tick a flow keeps all but 1/`FLOW_WINDOW_TICKS` of its total, so a steady flow settles
+// at what it moves in that many ticks; one that's had nothing go along it for this long is no
+// longer drawn
+const FLOW_WINDOW_TICKS: u32 = 100;
+
+// energy flowing at this rate (per `FLOW_WINDOW_TICKS`) gets the thickest arrow
+const FLOW_FULL_WIDTH: u32 = 5_000;
+
+const FLOW_COLOR: &str = "#ffaa00";
+
+pub fn enabled() -> bool {
+    memory::get_u32(&screeps::memory::ROOT, "visuals").is_some_and(|visuals| visuals > 0)
+}
+
+fn role_color(role: Role) -> &'static str {
+    ROLE_COLORS.iter().find(|(r, _)| *r == role).map_or("#cccccc", |(_, color)| color)
+}
+
+// label every creep we can see with its role, just above it
+pub fn draw_roles() {
+    if !enabled() {
+        return;
+    }
+    for creep in game::creeps().values() {
+        let pos = creep.pos();
+        let role = Role::of(&creep);
+        let style = TextStyle::default().color(role_color(role)).font(0.4);
+        RoomVisual::new(Some(pos.room_name())).text(pos.x().u8() as f32, pos.y().u8() as f32 - 0.6, role.as_str().to_string(), Some(style));
+    }
+}
+
+// note that energy went from one place to another
+pub fn note_flow(from: Position, to: Position, amount: u32) {
+    if from == to || !enabled() {
+        return;
+    }
+    let now = game::time();
+    FLOWS.with(|flows| {
+        let mut flows = flows.borrow_mut();
+        let flow = flows.entry((from, to)).or_insert((0, now));
+        *flow = (decayed(flow.0, now - flow.1) + amount, now);
+    });
+}
+
+// what's left of a flow's total `elapsed` ticks after it was last added to
+fn decayed(amount: u32, elapsed: u32) -> u32 {
+    let keep = (FLOW_WINDOW_TICKS - 1) as f64 / FLOW_WINDOW_TICKS as f64;
+    (amount as f64 * keep.powi(elapsed as i32)) as u32
+}
+
+// note where a creep has picked energy up from, for when it delivers it
+pub fn note_pickup(creep: &str, from: Position) {
+    if enabled() {
+        PICKED_UP.with(|picked_up| picked_up.borrow_mut().insert(creep.to_string(), from));
+    }
+}
+
+// note a creep delivering energy, as a flow from wherever it picked it up
+pub fn note_delivery(creep: &str, to: Position, amount: u32) {
+    if let Some(from) = PICKED_UP.with(|picked_up| picked_up.borrow().get(creep).copied()) {
+        note_flow(from, to, amount);
+    }
+}
+
+// draw every recent flow as an arrow, thicker for more energy
+pub fn draw_flows() {
+    if !enabled() {
+        return;
+    }
+    let now = game::time();
+    PICKED_UP.with(|picked_up| picked_up.borrow_mut().retain(|name, _| game::creeps().get(name.clone()).is_some()));
+    FLOWS.with(|flows| {
+        let mut flows = flows.borrow_mut();
+        flows.retain(|_, (_, tick)| now - *tick <= FLOW_WINDOW_TICKS);
+        for ((from, to), (amount, tick)) in flows.iter() {
+            // flows across rooms (remote hauling) don't fit in a single room's visual
+            if from.room_name() != to.room_name() {
+                continue;
+            }
+            let amount = decayed(*amount, now - *tick);
+            let width = 0.05 + 0.25 * amount.min(FLOW_FULL_WIDTH) as f32 / FLOW_FULL_WIDTH as f32;
+            let style = || LineStyle::default().color(FLOW_COLOR).width(width).opacity(0.6);
+            let (x1, y1) = (from.x().u8() as f32, from.y().u8() as f32);
+            let (x2, y2) = (to.x().u8() as f32, to.y().u8() as f32);
+            let length = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
+            let (dx, dy) = ((x2 - x1) / length, (y2 - y1) / length);
+            let visual = RoomVisual::new(Some(from.room_name()));
+            visual.line((x1, y1), (x2, y2), Some(style()));
+            // an arrowhead at the receiving end
+            let (bx, by) = (x2 - dx * 0.4, y2 - dy * 0.4);
+            visual.line((x2, y2), (bx - dy * 0.25, by + dx * 0.25), Some(style()));
+            visual.line((x2, y2), (bx + dy * 0.25, by - dx * 0.25), Some(style()));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_steady_flow_settles_at_its_rate_per_window() {
+        // 50 energy a tick is 5000 per window
+        let mut amount = 0;
+        for _ in 0..2000 {
+            amount = decayed(amount, 1) + 50;
+        }
+        assert!((4900..=5050).contains(&amount), "{}", amount);
+    }
+
+    #[test]
+    fn an_idle_flow_fades() {
+        assert_eq!(decayed(1000, 0), 1000);
+        assert!(decayed(1000, FLOW_WINDOW_TICKS) < 400);
+    }
+}
@@ -0,0 +1,52 @@
+use log::*;
+use screeps::{constants::ResourceType, objects::Room, prelude::*};
+
+use crate::memory;
+
+// after this many ticks without any energy coming in, spawning treats the room as an
+// emergency and bootstraps harvesters with whatever energy it has
+pub const STALL_TICKS: u32 = 300;
+
+// ticks at which we complain about a stalled economy, louder as it goes on
+const STALL_WARNINGS: [u32; 3] = [100, STALL_TICKS, 1000];
+
+fn stored_energy(room: &Room) -> u32 {
+    room.energy_available() + room.storage().map_or(0, |storage| storage.store().get_used_capacity(Some(ResourceType::Energy)))
+}
+
+// track the energy in each owned room's spawns, extensions and storage from tick to tick
+// (in `Memory.rooms[name]`) and count how long it's been since it last went up; a room that's
+// full counts as healthy, since there's nowhere for more energy to go
+pub fn check_economy(room: &Room) {
+    match room.controller() {
+        Some(controller) if controller.my() => {}
+        _ => return,
+    }
+    let room_memory = memory::room(room.name());
+    let energy = stored_energy(room);
+    let last_energy = memory::get_u32(&room_memory, "energy").unwrap_or(energy);
+    let stalled = memory::get_u32(&room_memory, "stalled").unwrap_or(0);
+
+    let stalled = if energy > last_energy || room.energy_available() >= room.energy_capacity_available() {
+        if stalled >= STALL_WARNINGS[0] {
+            info!("{}: energy income is back after {} ticks", room.name(), stalled);
+        }
+        0
+    } else {
+        stalled + 1
+    };
+
+    if stalled == STALL_WARNINGS[0] {
+        info!("{}: no energy income for {} ticks", room.name(), stalled);
+    } else if STALL_WARNINGS[1..].contains(&stalled) || (stalled > 0 && stalled % 5000 == 0) {
+        warn!("{}: economy stalled, no energy income for {} ticks!", room.name(), stalled);
+    }
+
+    memory::set_u32(&room_memory, "energy", energy);
+    memory::set_u32(&room_memory, "stalled", stalled);
+}
+
+pub fn is_stalled(room: &Room) -> bool {
+    let room_memory = memory::room(room.name());
+    memory::get_u32(&room_memory, "stalled").is_some_and(|stalled| stalled >= STALL_TICKS)
+}